@@ -0,0 +1,63 @@
+#!/usr/bin/env perl
+#
+# Generates src/unicode_tables.rs from the Unicode data bundled with Perl (Unicode::UCD). The
+# Unicode version is the one of the Perl running it.
+#
+#     perl scripts/gen_unicode_tables.pl > src/unicode_tables.rs
+
+use strict;
+use warnings;
+use Unicode::UCD qw(prop_invlist prop_values prop_value_aliases);
+
+# Inversion list to inclusive ranges, without the surrogates that are no Rust chars.
+sub ranges {
+    my @inv = prop_invlist($_[0]);
+    push @inv, 0x110000 if @inv % 2;
+    my @out;
+    for (my $i = 0; $i < @inv; $i += 2) {
+        my ($s, $e) = ($inv[$i], $inv[$i + 1] - 1);
+        if ($s <= 0xDFFF && $e >= 0xD800) {
+            push @out, [$s, 0xD7FF] if $s < 0xD800;
+            push @out, [0xE000, $e] if $e > 0xDFFF;
+        } else {
+            push @out, [$s, $e];
+        }
+    }
+    return @out;
+}
+
+sub table {
+    my ($name, @ranges) = @_;
+    my $out = "    (\n        \"$name\",\n        &[\n";
+    my $line = "           ";
+    for my $r (@ranges) {
+        my $item = sprintf(" (0x%X, 0x%X),", @$r);
+        if (length($line) + length($item) > 100) {
+            $out .= "$line\n";
+            $line = "           ";
+        }
+        $line .= $item;
+    }
+    $out .= "$line\n" if $line =~ /\S/;
+    $out .= "        ],\n    ),\n";
+    return $out;
+}
+
+print "// Generated by scripts/gen_unicode_tables.pl from the Unicode ", Unicode::UCD::UnicodeVersion(),
+    " data of Perl's Unicode::UCD, do not edit.\n";
+print "//\n// Each table is a sorted list of inclusive code point ranges.\n\n";
+print "/// General categories, both the two letter ones and their one letter unions.\n";
+print "pub(crate) const GENERAL_CATEGORIES: &[(&str, &[(u32, u32)])] = &[\n";
+for my $gc (qw(C Cc Cf Cn Co L Ll Lm Lo Lt Lu M Mc Me Mn N Nd Nl No P Pc Pd Pe Pf Pi Po Ps S Sc Sk Sm So Z Zl Zp Zs)) {
+    print table($gc, ranges("General_Category=$gc"));
+}
+print "];\n\n";
+print "/// Scripts by their long names.\n";
+print "pub(crate) const SCRIPTS: &[(&str, &[(u32, u32)])] = &[\n";
+# Long names, without the ones having no code points in this Unicode version.
+my @scripts = sort grep { scalar(prop_invlist("Script=$_")) }
+    map { my @a = prop_value_aliases("Script", $_); $a[1] // $a[0] // $_ } prop_values("Script");
+for my $s (@scripts) {
+    print table($s, ranges("Script=$s"));
+}
+print "];\n";
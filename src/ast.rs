@@ -3,6 +3,7 @@ use std::collections::HashSet;
 use crate::{
     common::{END_STATE, Incrementer, START_STATE},
     cond::{Cond, Literal},
+    evaluator::Evaluator,
     transition::{CaptureGroupInstruction, Transition},
 };
 
@@ -13,24 +14,106 @@ pub(crate) enum AstNode {
     Seq(Vec<AstNode>),
     Alt {
         options: Vec<AstNode>,
-        id: u64,
+        // `None` for groups that only alternate without capturing.
+        id: Option<u64>,
     },
+    // The parser guarantees `min <= max` when both are set.
     Repeat {
         min: Option<u64>,
         max: Option<u64>,
+        // Prefers the fewest iterations instead of the most.
+        is_lazy: bool,
         node: Box<AstNode>,
     },
-    Start,
-    End,
-    AnyChar,
+    Start {
+        multi_line: bool,
+    },
+    End {
+        multi_line: bool,
+    },
+    AnyChar {
+        dot_all: bool,
+    },
     CharGroup {
         is_negated: bool,
         chars: HashSet<Literal>,
+        ignore_case: bool,
+    },
+    CaptureRef {
+        id: u64,
+        ignore_case: bool,
+    },
+    // `\b`, or `\B` when negated.
+    WordBoundary {
+        is_negated: bool,
+        unicode: bool,
+    },
+    // `\<`
+    WordStart {
+        unicode: bool,
+    },
+    // `\>`
+    WordEnd {
+        unicode: bool,
+    },
+    // Atomic group or possessive repeat, matched on its own graph.
+    Atomic(Box<AstNode>),
+    // Zero-width assertions, matched on their own graph.
+    Lookahead {
+        node: Box<AstNode>,
+        is_negated: bool,
+    },
+    Lookbehind {
+        node: Box<AstNode>,
+        is_negated: bool,
     },
-    CaptureRef(u64),
 }
 
 impl AstNode {
+    /// Minimum and maximum number of tokens a match consumes, the maximum is `None` when unbounded.
+    pub(crate) fn len_range(&self) -> (usize, Option<usize>) {
+        match self {
+            Self::Root(inner) | Self::Atomic(inner) => inner.len_range(),
+            Self::Char(_) | Self::AnyChar { .. } | Self::CharGroup { .. } => (1, Some(1)),
+            // The sentinel token at the subject's ends, nothing at the line breaks inside it.
+            Self::Start { multi_line } | Self::End { multi_line } => {
+                (if *multi_line { 0 } else { 1 }, Some(1))
+            }
+            Self::Seq(seq) => seq.iter().fold((0, Some(0)), |(min, max), node| {
+                let (node_min, node_max) = node.len_range();
+                (min + node_min, max.zip(node_max).map(|(a, b)| a + b))
+            }),
+            Self::Alt { options, .. } => {
+                let ranges = options
+                    .iter()
+                    .map(|node| node.len_range())
+                    .collect::<Vec<_>>();
+                let min = ranges.iter().map(|(min, _)| *min).min().unwrap_or(0);
+                let max = ranges
+                    .iter()
+                    .try_fold(0, |max, (_, node_max)| node_max.map(|v| max.max(v)));
+                (min, max)
+            }
+            Self::Repeat { min, max, node, .. } => {
+                let (node_min, node_max) = node.len_range();
+                let min = node_min * min.unwrap_or(0) as usize;
+                let max = match (node_max, max) {
+                    (Some(0), _) => Some(0),
+                    (Some(node_max), Some(max)) => Some(node_max * *max as usize),
+                    _ => None,
+                };
+                (min, max)
+            }
+            // The captured text can be of any length.
+            Self::CaptureRef { .. } => (0, None),
+            Self::WordBoundary { .. }
+            | Self::WordStart { .. }
+            | Self::WordEnd { .. }
+            | Self::Lookahead { .. }
+            | Self::Lookbehind { .. } => (0, Some(0)),
+        }
+    }
+
     pub(crate) fn generate(&self) -> Vec<Transition> {
         let mut id_provider = Incrementer::new_from(END_STATE + 1);
         self.__generate(&mut id_provider, START_STATE, END_STATE)
@@ -83,7 +166,10 @@ impl AstNode {
                     inner_start,
                     Cond::None,
                     None,
-                    CaptureGroupInstruction::Start(*id),
+                    id.map_or(
+                        CaptureGroupInstruction::None,
+                        CaptureGroupInstruction::Start,
+                    ),
                 ));
 
                 for alt in options {
@@ -96,23 +182,21 @@ impl AstNode {
                     end_state,
                     Cond::None,
                     None,
-                    CaptureGroupInstruction::End(*id),
+                    id.map_or(CaptureGroupInstruction::None, CaptureGroupInstruction::End),
                 ));
 
                 transitions
             }
-            Self::Repeat { min, max, node } => {
+            Self::Repeat {
+                min,
+                max,
+                is_lazy,
+                node,
+            } => {
                 if max.map(|v| v == 0).unwrap_or(false) {
                     return vec![Transition::new(start_state, end_state)];
                 }
 
-                if max
-                    .and_then(|max_v| min.map(|min_v| max_v < min_v))
-                    .unwrap_or(false)
-                {
-                    panic!("Invalid repeat range");
-                }
-
                 let mut transitions = vec![];
                 let min = min.unwrap_or(0);
                 let req_len = if max.map(|v| v >= min).unwrap_or(true) && min > 1 {
@@ -125,10 +209,18 @@ impl AstNode {
                 let mut inner_start = id_provider.get();
                 let mut inner_end = id_provider.get();
 
+                // The evaluator tries the transitions of a state in generation order, so the edge
+                // pushed first is the preferred one: entering and repeating for greedy repeats,
+                // skipping and exiting for lazy ones.
+                if min == 0 && *is_lazy {
+                    // Skip - when 0 iter is allowed.
+                    transitions.push(Transition::new(start_state, end_state));
+                }
+
                 // Get to the inner start.
                 transitions.push(Transition::new(start_state, inner_start));
 
-                if min == 0 {
+                if min == 0 && !*is_lazy {
                     // Skip - when 0 iter is allowed.
                     transitions.push(Transition::new(start_state, end_state));
                 }
@@ -141,6 +233,11 @@ impl AstNode {
                     inner_end = id_provider.get();
                 }
 
+                if *is_lazy {
+                    // Get to inner end to end.
+                    transitions.push(Transition::new(inner_end, end_state));
+                }
+
                 // Repeat transition.
                 transitions.push(Transition::new_full(
                     inner_end,
@@ -154,27 +251,100 @@ impl AstNode {
                 // The actual inside graph.
                 transitions.append(&mut inner_t);
 
-                // Get to inner end to end.
-                transitions.push(Transition::new(inner_end, end_state));
+                if !*is_lazy {
+                    // Get to inner end to end.
+                    transitions.push(Transition::new(inner_end, end_state));
+                }
 
                 transitions
             }
-            Self::Start => vec![Transition::new_cond(start_state, end_state, Cond::Start)],
-            Self::End => vec![Transition::new_cond(start_state, end_state, Cond::End)],
-            Self::AnyChar => vec![Transition::new_cond(start_state, end_state, Cond::AnyChar)],
-            Self::CharGroup { is_negated, chars } => vec![Transition::new_cond(
+            Self::Start { multi_line } => vec![Transition::new_cond(
+                start_state,
+                end_state,
+                Cond::Start {
+                    multi_line: *multi_line,
+                },
+            )],
+            Self::End { multi_line } => vec![Transition::new_cond(
+                start_state,
+                end_state,
+                Cond::End {
+                    multi_line: *multi_line,
+                },
+            )],
+            Self::AnyChar { dot_all } => vec![Transition::new_cond(
+                start_state,
+                end_state,
+                Cond::AnyChar { dot_all: *dot_all },
+            )],
+            Self::WordBoundary {
+                is_negated,
+                unicode,
+            } => vec![Transition::new_cond(
+                start_state,
+                end_state,
+                Cond::WordBoundary {
+                    is_negated: *is_negated,
+                    unicode: *unicode,
+                },
+            )],
+            Self::WordStart { unicode } => vec![Transition::new_cond(
+                start_state,
+                end_state,
+                Cond::WordStart { unicode: *unicode },
+            )],
+            Self::WordEnd { unicode } => vec![Transition::new_cond(
+                start_state,
+                end_state,
+                Cond::WordEnd { unicode: *unicode },
+            )],
+            Self::CharGroup {
+                is_negated,
+                chars,
+                ignore_case,
+            } => vec![Transition::new_cond(
                 start_state,
                 end_state,
                 Cond::CharGroup {
                     chars: chars.clone(),
                     is_negated: *is_negated,
+                    ignore_case: *ignore_case,
+                },
+            )],
+            Self::CaptureRef { id, ignore_case } => vec![Transition::new_cond(
+                start_state,
+                end_state,
+                Cond::CaptureRef {
+                    id: *id,
+                    ignore_case: *ignore_case,
                 },
             )],
-            Self::CaptureRef(id) => vec![Transition::new_cond(
+            Self::Atomic(node) => vec![Transition::new_cond(
                 start_state,
                 end_state,
-                Cond::CaptureRef(*id),
+                Cond::Atomic(Box::new(Evaluator::new(node.generate()))),
             )],
+            Self::Lookahead { node, is_negated } => vec![Transition::new_cond(
+                start_state,
+                end_state,
+                Cond::Lookahead {
+                    evaluator: Box::new(Evaluator::new(node.generate())),
+                    is_negated: *is_negated,
+                },
+            )],
+            Self::Lookbehind { node, is_negated } => {
+                let (min_len, max_len) = node.len_range();
+                vec![Transition::new_cond(
+                    start_state,
+                    end_state,
+                    Cond::Lookbehind {
+                        evaluator: Box::new(Evaluator::new(node.generate())),
+                        is_negated: *is_negated,
+                        min_len,
+                        max_len,
+                    },
+                )]
+            }
         }
     }
 }
@@ -198,7 +368,7 @@ mod test {
                         AstNode::Char(Literal::Char('y')),
                     ]),
                 ],
-                id: 1,
+                id: Some(1),
             },
             AstNode::Alt {
                 options: vec![
@@ -211,7 +381,7 @@ mod test {
                         AstNode::Char(Literal::Char('2')),
                     ]),
                 ],
-                id: 2,
+                id: Some(2),
             },
             AstNode::Char(Literal::Char('c')),
         ])));
@@ -236,7 +406,7 @@ mod test {
                         AstNode::Char(Literal::Char('x')),
                     ]),
                 ],
-                id: 1,
+                id: Some(1),
             },
             AstNode::Alt {
                 options: vec![
@@ -249,7 +419,7 @@ mod test {
                         AstNode::Char(Literal::Char('2')),
                     ]),
                 ],
-                id: 2,
+                id: Some(2),
             },
             AstNode::Char(Literal::Char('c')),
         ])));
@@ -272,6 +442,7 @@ mod test {
                     AstNode::Repeat {
                         min: Some(0),
                         max: Some(0),
+                        is_lazy: false,
                         node: Box::new(AstNode::Char(Literal::Char('a'))),
                     },
                     AstNode::Char(Literal::Char('b')),
@@ -280,7 +451,7 @@ mod test {
                         AstNode::Char(Literal::Char('y')),
                     ]),
                 ],
-                id: 1,
+                id: Some(1),
             },
             AstNode::Alt {
                 options: vec![
@@ -293,7 +464,7 @@ mod test {
                         AstNode::Char(Literal::Char('2')),
                     ]),
                 ],
-                id: 2,
+                id: Some(2),
             },
             AstNode::Char(Literal::Char('c')),
         ])));
@@ -303,6 +474,19 @@ mod test {
         create_dot_file_from_transitions(&transitions);
     }
 
+    #[test]
+    fn test_len_range() {
+        let len_range = |pattern: &str| Parser::parse_regex_str(pattern).unwrap().len_range();
+
+        assert_eq!((3, Some(3)), len_range("bob"));
+        assert_eq!((1, Some(2)), len_range("a|bc"));
+        assert_eq!((2, Some(6)), len_range("(ab){1,3}"));
+        assert_eq!((1, None), len_range("a+"));
+        assert_eq!((1, Some(1)), len_range("\\ba(?=x)\\b"));
+        assert_eq!((2, Some(2)), len_range("^a"));
+        assert_eq!((1, None), len_range("(a)\\1"));
+    }
+
     #[test]
     fn test_transition_for_loop() {
         let ast = Parser::parse_regex_str("x{2}").unwrap();
@@ -26,15 +26,36 @@ impl Capturer {
         assert!(id == self.currents.pop().unwrap());
     }
 
+    /// Copy of the captures without the open groups, so the chars a lookaround scans are not added
+    /// to the groups enclosing it.
+    pub(crate) fn detached(&self) -> Self {
+        Self {
+            captures: self.captures.clone(),
+            currents: vec![],
+        }
+    }
+
+    /// Takes over the groups captured by a `detached` copy, the open groups are left as they are.
+    pub(crate) fn merge_detached(&self, detached: Capturer) -> Self {
+        let mut merged = self.clone();
+        for (id, capture) in detached.captures {
+            if !merged.currents.contains(&id) {
+                merged.captures.insert(id, capture);
+            }
+        }
+        merged
+    }
+
     pub(crate) fn push(&mut self, tokens: &[Token]) {
+        if self.currents.is_empty() {
+            return;
+        }
+
         for token in tokens {
-            match token {
-                Token::Char(c) => {
-                    for id in &self.currents {
-                        self.captures.get_mut(id).unwrap().push(*c);
-                    }
+            if let Token::Char(c) = token {
+                for id in &self.currents {
+                    self.captures.get_mut(id).unwrap().push(*c);
                 }
-                _ => {}
             }
         }
     }
@@ -2,14 +2,32 @@ use crate::token::Token;
 
 pub(crate) const EXIT_CODE_SUCCESS: i32 = 0;
 pub(crate) const EXIT_CODE_NO_MATCH: i32 = 1;
+pub(crate) const EXIT_CODE_ERROR: i32 = 2;
 
 pub(crate) const START_STATE: u64 = 0;
 pub(crate) const END_STATE: u64 = 1;
 
-pub(crate) type Error = Box<dyn std::error::Error + Send + Sync>;
+/// Word constituent chars, as in `\w`.
+pub(crate) fn is_word_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_'
+}
+
+/// `\w` in Unicode mode.
+pub(crate) fn is_unicode_word_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+/// Whether the token is a word char, the line sentinels and out of range positions are not.
+pub(crate) fn is_word_token(token: Option<&Token>, unicode: bool) -> bool {
+    match token {
+        Some(Token::Char(c)) if unicode => is_unicode_word_char(*c),
+        Some(Token::Char(c)) => is_word_char(*c),
+        _ => false,
+    }
+}
 
 pub(crate) fn str_to_tokens(s: &str) -> Vec<Token> {
-    let mut out = s.chars().map(|c| Token::Char(c)).collect::<Vec<_>>();
+    let mut out = s.chars().map(Token::Char).collect::<Vec<_>>();
 
     out.insert(0, Token::Start);
     out.push(Token::End);
@@ -30,14 +48,19 @@ impl Incrementer {
         Self { v }
     }
 
+    /// The value the next `get` call returns.
+    pub(crate) fn peek(&self) -> u64 {
+        self.v
+    }
+
     pub(crate) fn get(&mut self) -> u64 {
         self.v += 1;
         self.v - 1
     }
 }
 
-pub(crate) fn merge_overlapping_match_ranges(ranges: &Vec<(usize, usize)>) -> Vec<(usize, usize)> {
-    let mut ranges = ranges.clone();
+pub(crate) fn merge_overlapping_match_ranges(ranges: &[(usize, usize)]) -> Vec<(usize, usize)> {
+    let mut ranges = ranges.to_vec();
     ranges.sort();
 
     let mut out: Vec<(usize, usize)> = vec![];
@@ -46,7 +69,8 @@ pub(crate) fn merge_overlapping_match_ranges(ranges: &Vec<(usize, usize)>) -> Ve
         if out.is_empty() || out.last().unwrap().1 < start {
             out.push((start, end));
         } else {
-            out.last_mut().unwrap().1 = end;
+            let last = out.last_mut().unwrap();
+            last.1 = last.1.max(end);
         }
     }
 
@@ -1,16 +1,24 @@
-use std::collections::{HashMap, HashSet};
+use std::collections::HashSet;
 
-use crate::token::Token;
+use crate::{
+    capturer::Capturer,
+    common::{is_unicode_word_char, is_word_char, is_word_token},
+    evaluator::Evaluator,
+    token::Token,
+    unicode::ranges_contain,
+};
 
 pub(crate) enum MatchResult {
     Match(usize),
+    // A sub-match that consumed the tokens and updated the captures on its own.
+    MatchWithCaptures(usize, Capturer),
     NoMatch,
 }
 
 impl MatchResult {
     fn is_success(&self) -> bool {
         match self {
-            Self::Match(_) => true,
+            Self::Match(_) | Self::MatchWithCaptures(..) => true,
             Self::NoMatch => false,
         }
     }
@@ -19,63 +27,181 @@ impl MatchResult {
 #[derive(Debug, Clone, Hash, PartialEq, Eq)]
 pub(crate) enum Literal {
     Char(char),
-    Range { start: char, end: char },
+    Range {
+        start: char,
+        end: char,
+    },
     Numeric,
+    NonNumeric,
     Alphanumeric,
+    NonAlphanumeric,
+    Whitespace,
+    NonWhitespace,
+    HorizontalWhitespace,
+    VerticalWhitespace,
+    Posix(PosixClass),
+    // Unicode-aware `\w` and `\s` (`--unicode`).
+    UnicodeAlphanumeric,
+    NonUnicodeAlphanumeric,
+    UnicodeWhitespace,
+    NonUnicodeWhitespace,
+    // `\p{...}`, or `\P{...}` when negated.
+    Property {
+        name: &'static str,
+        ranges: &'static [(u32, u32)],
+        is_negated: bool,
+    },
+}
+
+/// The `[:name:]` classes of bracket expressions, in the C locale.
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq)]
+pub(crate) enum PosixClass {
+    Alnum,
+    Alpha,
+    Blank,
+    Cntrl,
+    Digit,
+    Graph,
+    Lower,
+    Print,
+    Punct,
+    Space,
+    Upper,
+    Xdigit,
+}
+
+impl PosixClass {
+    const ALL: [(&'static str, Self); 12] = [
+        ("alnum", Self::Alnum),
+        ("alpha", Self::Alpha),
+        ("blank", Self::Blank),
+        ("cntrl", Self::Cntrl),
+        ("digit", Self::Digit),
+        ("graph", Self::Graph),
+        ("lower", Self::Lower),
+        ("print", Self::Print),
+        ("punct", Self::Punct),
+        ("space", Self::Space),
+        ("upper", Self::Upper),
+        ("xdigit", Self::Xdigit),
+    ];
+
+    pub(crate) fn from_name(name: &str) -> Option<Self> {
+        Self::ALL
+            .iter()
+            .find(|(class_name, _)| *class_name == name)
+            .map(|(_, class)| *class)
+    }
+
+    fn name(&self) -> &'static str {
+        Self::ALL
+            .iter()
+            .find(|(_, class)| class == self)
+            .map(|(class_name, _)| *class_name)
+            .unwrap()
+    }
+
+    fn contains(&self, c: char) -> bool {
+        match self {
+            Self::Alnum => c.is_ascii_alphanumeric(),
+            Self::Alpha => c.is_ascii_alphabetic(),
+            Self::Blank => is_horizontal_whitespace(c),
+            Self::Cntrl => c.is_ascii_control(),
+            Self::Digit => c.is_ascii_digit(),
+            Self::Graph => c.is_ascii_graphic(),
+            Self::Lower => c.is_ascii_lowercase(),
+            Self::Print => c.is_ascii_graphic() || c == ' ',
+            Self::Punct => c.is_ascii_punctuation(),
+            Self::Space => is_whitespace(c),
+            Self::Upper => c.is_ascii_uppercase(),
+            Self::Xdigit => c.is_ascii_hexdigit(),
+        }
+    }
+}
+
+/// `[[:space:]]`: space, `\t`, `\n`, `\v`, `\f` and `\r`.
+fn is_whitespace(c: char) -> bool {
+    is_horizontal_whitespace(c) || is_vertical_whitespace(c)
+}
+
+fn is_horizontal_whitespace(c: char) -> bool {
+    matches!(c, ' ' | '\t')
+}
+
+fn is_vertical_whitespace(c: char) -> bool {
+    matches!(c, '\n' | '\x0B' | '\x0C' | '\r')
+}
+
+/// The char itself and its single char lower and upper case forms.
+fn case_variants(c: char) -> Vec<char> {
+    let mut variants = vec![c];
+    let lower = c.to_lowercase().collect::<Vec<_>>();
+    let upper = c.to_uppercase().collect::<Vec<_>>();
+
+    for folded in [lower, upper] {
+        if folded.len() == 1 && !variants.contains(&folded[0]) {
+            variants.push(folded[0]);
+        }
+    }
+
+    variants
+}
+
+fn chars_eq_ignore_case(lhs: char, rhs: char) -> bool {
+    lhs == rhs || lhs.to_lowercase().eq(rhs.to_lowercase())
 }
 
 impl Literal {
     fn to_label(&self) -> String {
         match self {
             Self::Alphanumeric => "\\w".to_string(),
+            Self::NonAlphanumeric => "\\W".to_string(),
             Self::Char(c) => c.to_string(),
             Self::Range { start, end } => format!("{}-{}", start, end),
             Self::Numeric => "\\d".to_string(),
+            Self::NonNumeric => "\\D".to_string(),
+            Self::Whitespace => "\\s".to_string(),
+            Self::NonWhitespace => "\\S".to_string(),
+            Self::HorizontalWhitespace => "\\h".to_string(),
+            Self::VerticalWhitespace => "\\v".to_string(),
+            Self::Posix(class) => format!("[:{}:]", class.name()),
+            Self::UnicodeAlphanumeric => "(?u)\\w".to_string(),
+            Self::NonUnicodeAlphanumeric => "(?u)\\W".to_string(),
+            Self::UnicodeWhitespace => "(?u)\\s".to_string(),
+            Self::NonUnicodeWhitespace => "(?u)\\S".to_string(),
+            Self::Property {
+                name, is_negated, ..
+            } => format!("\\{}{{{}}}", if *is_negated { "P" } else { "p" }, name),
         }
     }
 
-    pub(crate) fn is_match(&self, token: Option<&Token>) -> MatchResult {
+    fn contains(&self, c: char) -> bool {
         match self {
-            Self::Alphanumeric => match token {
-                Some(Token::Char(c)) => {
-                    if c.is_ascii_alphanumeric() || c == &'_' {
-                        MatchResult::Match(1)
-                    } else {
-                        MatchResult::NoMatch
-                    }
-                }
-                _ => MatchResult::NoMatch,
-            },
-            Self::Char(c) => match token {
-                Some(Token::Char(tc)) => {
-                    if tc == c {
-                        MatchResult::Match(1)
-                    } else {
-                        MatchResult::NoMatch
-                    }
-                }
-                _ => MatchResult::NoMatch,
-            },
-            Self::Numeric => match token {
-                Some(Token::Char(c)) => {
-                    if c.is_ascii_digit() {
-                        MatchResult::Match(1)
-                    } else {
-                        MatchResult::NoMatch
-                    }
-                }
-                _ => MatchResult::NoMatch,
-            },
-            Self::Range { start, end } => match token {
-                Some(Token::Char(c)) => {
-                    if c >= start && c <= end {
-                        MatchResult::Match(1)
-                    } else {
-                        MatchResult::NoMatch
-                    }
-                }
-                _ => MatchResult::NoMatch,
-            },
+            Self::Alphanumeric => is_word_char(c),
+            Self::NonAlphanumeric => !is_word_char(c),
+            Self::Char(literal_c) => *literal_c == c,
+            Self::Range { start, end } => *start <= c && c <= *end,
+            Self::Numeric => c.is_ascii_digit(),
+            Self::NonNumeric => !c.is_ascii_digit(),
+            Self::Whitespace => is_whitespace(c),
+            Self::NonWhitespace => !is_whitespace(c),
+            Self::HorizontalWhitespace => is_horizontal_whitespace(c),
+            Self::VerticalWhitespace => is_vertical_whitespace(c),
+            Self::Posix(class) => class.contains(c),
+            Self::UnicodeAlphanumeric => is_unicode_word_char(c),
+            Self::NonUnicodeAlphanumeric => !is_unicode_word_char(c),
+            Self::UnicodeWhitespace => c.is_whitespace(),
+            Self::NonUnicodeWhitespace => !c.is_whitespace(),
+            Self::Property {
+                ranges, is_negated, ..
+            } => ranges_contain(ranges, c) != *is_negated,
+        }
+    }
+
+    pub(crate) fn is_match(&self, token: Option<&Token>) -> MatchResult {
+        match token {
+            Some(Token::Char(c)) if self.contains(*c) => MatchResult::Match(1),
+            _ => MatchResult::NoMatch,
         }
     }
 }
@@ -83,24 +209,66 @@ impl Literal {
 #[derive(Debug)]
 pub(crate) enum Cond {
     Char(Literal),
-    AnyChar,
+    AnyChar {
+        dot_all: bool,
+    },
     CharGroup {
         chars: HashSet<Literal>,
         is_negated: bool,
+        ignore_case: bool,
+    },
+    Start {
+        multi_line: bool,
+    },
+    End {
+        multi_line: bool,
     },
-    Start,
-    End,
     None,
-    CaptureRef(u64),
+    CaptureRef {
+        id: u64,
+        ignore_case: bool,
+    },
+    // Zero-width conditions on the chars before and after the position. Word chars are the ones of
+    // `\w`, Unicode ones with `--unicode`.
+    WordBoundary {
+        is_negated: bool,
+        unicode: bool,
+    },
+    WordStart {
+        unicode: bool,
+    },
+    WordEnd {
+        unicode: bool,
+    },
+    // Commits to the first match of the sub-expression, it is never backtracked into.
+    Atomic(Box<Evaluator>),
+    // The sub-expression matches (or not, when negated) right after the position.
+    Lookahead {
+        evaluator: Box<Evaluator>,
+        is_negated: bool,
+    },
+    // The sub-expression matches (or not, when negated) ending right at the position.
+    Lookbehind {
+        evaluator: Box<Evaluator>,
+        is_negated: bool,
+        // Token count range of the sub-expression, `None` when unbounded.
+        min_len: usize,
+        max_len: Option<usize>,
+    },
 }
 
 impl Cond {
     pub(crate) fn to_label(&self) -> String {
         match self {
             Self::Char(t) => t.to_label(),
-            Self::CharGroup { chars, is_negated } => {
+            Self::CharGroup {
+                chars,
+                is_negated,
+                ignore_case,
+            } => {
                 format!(
-                    "[{}{}]",
+                    "{}[{}{}]",
+                    if *ignore_case { "(?i)" } else { "" },
                     if *is_negated { "^" } else { "" },
                     chars
                         .iter()
@@ -110,27 +278,70 @@ impl Cond {
                 )
             }
             Self::None => "-".to_string(),
-            Self::Start => "^".to_string(),
-            Self::End => "$".to_string(),
-            Self::AnyChar => ".".to_string(),
-            Self::CaptureRef(id) => format!("ref{}", id),
+            Self::Start { multi_line } => format!("{}^", if *multi_line { "(?m)" } else { "" }),
+            Self::End { multi_line } => format!("{}$", if *multi_line { "(?m)" } else { "" }),
+            Self::AnyChar { dot_all } => format!("{}.", if *dot_all { "(?s)" } else { "" }),
+            Self::CaptureRef { id, ignore_case } => {
+                format!("{}ref{}", if *ignore_case { "(?i)" } else { "" }, id)
+            }
+            Self::WordBoundary {
+                is_negated,
+                unicode,
+            } => format!(
+                "{}{}",
+                if *unicode { "(?u)" } else { "" },
+                if *is_negated { "\\B" } else { "\\b" }
+            ),
+            Self::WordStart { unicode } => format!("{}\\<", if *unicode { "(?u)" } else { "" }),
+            Self::WordEnd { unicode } => format!("{}\\>", if *unicode { "(?u)" } else { "" }),
+            Self::Atomic(_) => "(?>...)".to_string(),
+            Self::Lookahead { is_negated, .. } => {
+                format!("(?{}...)", if *is_negated { "!" } else { "=" })
+            }
+            Self::Lookbehind { is_negated, .. } => {
+                format!("(?<{}...)", if *is_negated { "!" } else { "=" })
+            }
         }
     }
 
-    pub(crate) fn is_match(
-        &self,
-        tokens: &[Token],
-        captures: &HashMap<u64, String>,
-    ) -> MatchResult {
+    /// Matches at `pos` of the line's `tokens`. Only the assertions look at the tokens before `pos`.
+    pub(crate) fn is_match(&self, line: &[Token], pos: usize, capturer: &Capturer) -> MatchResult {
+        let tokens = &line[pos..];
+        // Whether the chars before and after the position are word chars.
+        let word_around = |unicode: bool| {
+            (
+                pos > 0 && is_word_token(line.get(pos - 1), unicode),
+                is_word_token(tokens.first(), unicode),
+            )
+        };
+        let zero_width = |is_match: bool| {
+            if is_match {
+                MatchResult::Match(0)
+            } else {
+                MatchResult::NoMatch
+            }
+        };
+
         match self {
             Self::Char(t) => t.is_match(tokens.first()),
             Self::None => MatchResult::Match(0),
-            Self::CharGroup { chars, is_negated } => match tokens.first() {
+            Self::CharGroup {
+                chars,
+                is_negated,
+                ignore_case,
+            } => match tokens.first() {
                 Some(Token::Char(c)) => {
-                    if chars
-                        .iter()
-                        .any(|group_c| group_c.is_match(Some(&Token::Char(*c))).is_success())
-                        ^ is_negated
+                    let candidates = if *ignore_case {
+                        case_variants(*c)
+                    } else {
+                        vec![*c]
+                    };
+
+                    if chars.iter().any(|group_c| {
+                        candidates
+                            .iter()
+                            .any(|c| group_c.is_match(Some(&Token::Char(*c))).is_success())
+                    }) ^ is_negated
                     {
                         MatchResult::Match(1)
                     } else {
@@ -139,27 +350,41 @@ impl Cond {
                 }
                 _ => MatchResult::NoMatch,
             },
-            Self::Start => match tokens.first() {
+            // The subject's ends are sentinel tokens, the line breaks inside it are matched without
+            // consuming anything.
+            Self::Start { multi_line } => match tokens.first() {
                 Some(Token::Start) => MatchResult::Match(1),
+                _ if *multi_line && pos > 0 && matches!(line[pos - 1], Token::Char('\n')) => {
+                    MatchResult::Match(0)
+                }
                 _ => MatchResult::NoMatch,
             },
-            Self::End => match tokens.first() {
+            Self::End { multi_line } => match tokens.first() {
                 Some(Token::End) => MatchResult::Match(1),
+                Some(Token::Char('\n')) if *multi_line => MatchResult::Match(0),
                 _ => MatchResult::NoMatch,
             },
-            Self::AnyChar => match tokens.first() {
-                Some(Token::Char(_)) => MatchResult::Match(1),
+            Self::AnyChar { dot_all } => match tokens.first() {
+                Some(Token::Char(c)) if *dot_all || *c != '\n' => MatchResult::Match(1),
                 _ => MatchResult::NoMatch,
             },
-            Self::CaptureRef(id) => match captures.get(id) {
+            Self::CaptureRef { id, ignore_case } => match capturer.captures.get(id) {
                 Some(capture) => {
-                    if tokens.len() < capture.len() {
+                    // Tokens are chars, captures are strings.
+                    let capture_len = capture.chars().count();
+                    if tokens.len() < capture_len {
                         MatchResult::NoMatch
                     } else {
                         for (capture_c, token) in capture.chars().zip(tokens) {
                             match token {
                                 Token::Char(token_c) => {
-                                    if token_c != &capture_c {
+                                    let is_eq = if *ignore_case {
+                                        chars_eq_ignore_case(*token_c, capture_c)
+                                    } else {
+                                        *token_c == capture_c
+                                    };
+
+                                    if !is_eq {
                                         return MatchResult::NoMatch;
                                     }
                                 }
@@ -167,11 +392,77 @@ impl Cond {
                             }
                         }
 
-                        MatchResult::Match(capture.len())
+                        MatchResult::Match(capture_len)
                     }
                 }
                 None => MatchResult::NoMatch,
             },
+            Self::WordBoundary {
+                is_negated,
+                unicode,
+            } => {
+                let (is_word_before, is_word_after) = word_around(*unicode);
+                zero_width((is_word_before != is_word_after) != *is_negated)
+            }
+            Self::WordStart { unicode } => {
+                let (is_word_before, is_word_after) = word_around(*unicode);
+                zero_width(!is_word_before && is_word_after)
+            }
+            Self::WordEnd { unicode } => {
+                let (is_word_before, is_word_after) = word_around(*unicode);
+                zero_width(is_word_before && !is_word_after)
+            }
+            Self::Atomic(evaluator) => {
+                match evaluator.first_match(line, pos, capturer.clone(), |_| true) {
+                    Some((step, new_capturer)) => {
+                        MatchResult::MatchWithCaptures(step, new_capturer)
+                    }
+                    None => MatchResult::NoMatch,
+                }
+            }
+            Self::Lookahead {
+                evaluator,
+                is_negated,
+            } => Self::assertion_result(
+                capturer,
+                evaluator.first_match(line, pos, capturer.detached(), |_| true),
+                *is_negated,
+            ),
+            Self::Lookbehind {
+                evaluator,
+                is_negated,
+                min_len,
+                max_len,
+            } => {
+                if pos < *min_len {
+                    return Self::assertion_result(capturer, None, *is_negated);
+                }
+
+                // Only the starts the sub-expression can reach from are tried, an unbounded one
+                // (like `(?<=a+)`) has to try all of them, which is slow on long lines.
+                let first_start = max_len.map_or(0, |max_len| pos.saturating_sub(max_len));
+                // The closest start first, the match has to end right at `pos`.
+                let found = (first_start..=pos - min_len).rev().find_map(|start| {
+                    evaluator
+                        .first_match(line, start, capturer.detached(), |len| start + len == pos)
+                });
+                Self::assertion_result(capturer, found, *is_negated)
+            }
+        }
+    }
+
+    /// Zero-width result of a lookaround. Captures of a positive assertion are kept.
+    fn assertion_result(
+        capturer: &Capturer,
+        found: Option<(usize, Capturer)>,
+        is_negated: bool,
+    ) -> MatchResult {
+        match (found, is_negated) {
+            (Some((_, new_capturer)), false) => {
+                MatchResult::MatchWithCaptures(0, capturer.merge_detached(new_capturer))
+            }
+            (None, true) => MatchResult::Match(0),
+            _ => MatchResult::NoMatch,
         }
     }
 }
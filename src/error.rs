@@ -0,0 +1,74 @@
+use thiserror::Error;
+
+/// Errors of pattern parsing. Each variant carries the char offset in the pattern where the
+/// problem was detected.
+#[derive(Debug, Error, PartialEq)]
+pub(crate) enum PatternError {
+    #[error("unexpected end of pattern")]
+    UnexpectedEnd { pos: usize },
+    #[error("expected {expected}, found {found}")]
+    UnexpectedToken {
+        pos: usize,
+        expected: String,
+        found: String,
+    },
+    #[error("unmatched ( or \\(")]
+    UnmatchedOpenParen { pos: usize },
+    #[error("unmatched ) or \\)")]
+    UnmatchedCloseParen { pos: usize },
+    #[error("unmatched [, [^, [:, [., or [=")]
+    UnmatchedBracket { pos: usize },
+    #[error("trailing backslash (\\)")]
+    TrailingBackslash { pos: usize },
+    #[error("invalid number in repetition")]
+    InvalidNumber { pos: usize },
+    #[error("invalid repetition range {{{min},{max}}}")]
+    InvalidRepeatRange { pos: usize, min: u64, max: u64 },
+    #[error("invalid back reference \\{id}")]
+    InvalidBackReference { pos: usize, id: u64 },
+    #[error("unknown inline flag '{flag}'")]
+    UnknownFlag { pos: usize, flag: char },
+    #[error("invalid range end")]
+    InvalidRange { pos: usize },
+    #[error("invalid character class '{name}'")]
+    UnknownCharClass { pos: usize, name: String },
+    #[error("invalid escape sequence")]
+    InvalidEscape { pos: usize },
+    #[error("unknown property '{name}'")]
+    UnknownProperty { pos: usize, name: String },
+    #[error("invalid group name")]
+    InvalidGroupName { pos: usize },
+    #[error("duplicate group name '{name}'")]
+    DuplicateGroupName { pos: usize, name: String },
+    #[error("reference to undefined group name '{name}'")]
+    UndefinedGroupName { pos: usize, name: String },
+}
+
+impl PatternError {
+    pub(crate) fn pos(&self) -> usize {
+        match self {
+            Self::UnexpectedEnd { pos }
+            | Self::UnexpectedToken { pos, .. }
+            | Self::UnmatchedOpenParen { pos }
+            | Self::UnmatchedCloseParen { pos }
+            | Self::UnmatchedBracket { pos }
+            | Self::TrailingBackslash { pos }
+            | Self::InvalidNumber { pos }
+            | Self::InvalidRepeatRange { pos, .. }
+            | Self::InvalidBackReference { pos, .. }
+            | Self::UnknownFlag { pos, .. }
+            | Self::InvalidRange { pos }
+            | Self::UnknownCharClass { pos, .. }
+            | Self::InvalidEscape { pos }
+            | Self::UnknownProperty { pos, .. }
+            | Self::InvalidGroupName { pos }
+            | Self::DuplicateGroupName { pos, .. }
+            | Self::UndefinedGroupName { pos, .. } => *pos,
+        }
+    }
+
+    /// Renders the pattern with a `^` under the offending char.
+    pub(crate) fn caret_diagnostic(&self, pattern: &str) -> String {
+        format!("  {}\n  {}^", pattern, " ".repeat(self.pos()))
+    }
+}
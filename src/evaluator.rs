@@ -2,7 +2,7 @@ use std::collections::{HashMap, HashSet};
 
 use crate::{
     capturer::Capturer,
-    common::{END_STATE, Incrementer},
+    common::{END_STATE, Incrementer, START_STATE},
     cond::MatchResult,
     token::Token,
     transition::{CaptureGroupInstruction, Transition},
@@ -16,20 +16,33 @@ pub(crate) enum EvalMatchResult {
 impl EvalMatchResult {
     #[allow(dead_code)]
     pub(crate) fn is_match(&self) -> bool {
-        match self {
-            Self::Match { .. } => true,
-            _ => false,
-        }
+        matches!(self, Self::Match { .. })
     }
 }
 
+#[derive(Debug)]
 pub(crate) struct Evaluator {
     transitions: Vec<Transition>,
+    // Indices into `transitions` grouped by `from_state`, keeping the generation order. That order
+    // is the priority: the first path reaching the end state is the match.
+    state_transitions: HashMap<u64, Vec<usize>>,
+    loop_start_transitions: HashSet<(u64, u64)>,
 }
 
 impl Evaluator {
     pub(crate) fn new(transitions: Vec<Transition>) -> Self {
-        Self { transitions }
+        let mut state_transitions: HashMap<u64, Vec<usize>> = HashMap::new();
+        for (i, tr) in transitions.iter().enumerate() {
+            state_transitions.entry(tr.from_state).or_default().push(i);
+        }
+
+        let loop_start_transitions = Self::get_loop_start_transitions(&transitions);
+
+        Self {
+            transitions,
+            state_transitions,
+            loop_start_transitions,
+        }
     }
 
     /**
@@ -39,69 +52,21 @@ impl Evaluator {
      * - when max-trans end node is reached from a non-max-trans trans - the number is increased
      * - each max-counting is tied to this number
      */
+    #[allow(dead_code)]
     pub(crate) fn is_match(&self, chars: &[Token]) -> EvalMatchResult {
-        let loop_start_transitions = self.get_loop_start_transitions();
         let mut matches = vec![];
 
         let mut offset = 0;
 
-        'main_loop: while offset < chars.len() {
-            let mut visit_counter: HashMap<u64, u64> = HashMap::new();
-            let mut id_provider = Incrementer::new();
-            let mut stack = vec![(&chars[offset..], id_provider.get(), 0u64, Capturer::new())];
-
-            while let Some((stream, loop_id, current_state, capturer)) = stack.pop() {
-                if current_state == END_STATE {
-                    matches.push((offset, chars.len() - stream.len()));
+        while offset < chars.len() {
+            match self.first_match(chars, offset, Capturer::new(), |_| true) {
+                Some((len, _)) => {
+                    matches.push((offset, offset + len));
                     // `max(offset + 1)` ensures the scanner is not stuck with valid empty matches.
-                    offset = (chars.len() - stream.len()).max(offset + 1);
-                    continue 'main_loop;
-                }
-
-                let available_transitions = self.get_available_transitions(current_state);
-
-                for tr in available_transitions.iter().rev() {
-                    // Increase loop_id when starts a loop.
-                    let loop_id = if loop_start_transitions.contains(&(tr.from_state, tr.to_state))
-                    {
-                        id_provider.get()
-                    } else {
-                        loop_id
-                    };
-
-                    // Block if already reached max use.
-                    if let Some(max_use) = tr.max_use {
-                        let current_use = visit_counter.get(&current_state).unwrap_or(&0);
-                        if current_use >= &max_use {
-                            continue;
-                        }
-                    }
-
-                    match tr.cond.is_match(stream, &capturer.captures) {
-                        MatchResult::Match(step) => {
-                            if tr.max_use.is_some() {
-                                *visit_counter.entry(current_state).or_default() += 1;
-                            }
-
-                            let mut new_capturer = capturer.clone();
-                            new_capturer.push(&stream[..step]);
-
-                            match tr.capture_group_ins {
-                                CaptureGroupInstruction::Start(id) => {
-                                    new_capturer.start_capture(id)
-                                }
-                                CaptureGroupInstruction::End(id) => new_capturer.end_capture(id),
-                                CaptureGroupInstruction::None => {}
-                            }
-
-                            stack.push((&stream[step..], loop_id, tr.to_state, new_capturer));
-                        }
-                        MatchResult::NoMatch => {}
-                    }
+                    offset = (offset + len).max(offset + 1);
                 }
+                None => offset += 1,
             }
-
-            offset += 1;
         }
 
         if matches.is_empty() {
@@ -111,28 +76,123 @@ impl Evaluator {
         }
     }
 
-    fn get_available_transitions(&self, start_state: u64) -> Vec<&Transition> {
-        let mut transitions = vec![];
+    /// Runs the search anchored at `start` of `chars`, starting with the given captures. Returns the
+    /// length of the first match (in priority order) that `accept` approves, along with the
+    /// captures of its path.
+    pub(crate) fn first_match<F>(
+        &self,
+        chars: &[Token],
+        start: usize,
+        capturer: Capturer,
+        accept: F,
+    ) -> Option<(usize, Capturer)>
+    where
+        F: Fn(usize) -> bool,
+    {
+        let mut visit_counter: HashMap<u64, u64> = HashMap::new();
+        let mut id_provider = Incrementer::new();
+        // The last item is the states reached since the last consumed token. Getting back to one of
+        // them is a loop that matches nothing (like `(a?)*` or `\b*`), it would never end.
+        let mut stack = vec![(
+            start,
+            id_provider.get(),
+            START_STATE,
+            capturer,
+            vec![START_STATE],
+        )];
+
+        while let Some((pos, loop_id, current_state, capturer, zero_width_states)) = stack.pop() {
+            if current_state == END_STATE {
+                let len = pos - start;
+                if accept(len) {
+                    return Some((len, capturer));
+                }
+                continue;
+            }
+
+            let available_transitions = self.get_available_transitions(current_state);
+
+            for tr in available_transitions.iter().rev() {
+                // Increase loop_id when starts a loop.
+                let loop_id = if self
+                    .loop_start_transitions
+                    .contains(&(tr.from_state, tr.to_state))
+                {
+                    id_provider.get()
+                } else {
+                    loop_id
+                };
+
+                // Block if already reached max use.
+                if let Some(max_use) = tr.max_use {
+                    let current_use = visit_counter.get(&current_state).unwrap_or(&0);
+                    if current_use >= &max_use {
+                        continue;
+                    }
+                }
+
+                let (step, mut new_capturer) = match tr.cond.is_match(chars, pos, &capturer) {
+                    MatchResult::Match(step) => {
+                        let mut new_capturer = capturer.clone();
+                        new_capturer.push(&chars[pos..pos + step]);
+                        (step, new_capturer)
+                    }
+                    // The sub-match already recorded what it consumed.
+                    MatchResult::MatchWithCaptures(step, new_capturer) => (step, new_capturer),
+                    MatchResult::NoMatch => continue,
+                };
+
+                let zero_width_states = if step == 0 {
+                    if zero_width_states.contains(&tr.to_state) {
+                        continue;
+                    }
+                    let mut states = zero_width_states.clone();
+                    states.push(tr.to_state);
+                    states
+                } else {
+                    vec![tr.to_state]
+                };
+
+                if tr.max_use.is_some() {
+                    *visit_counter.entry(current_state).or_default() += 1;
+                }
+
+                match tr.capture_group_ins {
+                    CaptureGroupInstruction::Start(id) => new_capturer.start_capture(id),
+                    CaptureGroupInstruction::End(id) => new_capturer.end_capture(id),
+                    CaptureGroupInstruction::None => {}
+                }
 
-        for t in &self.transitions {
-            if t.from_state == start_state {
-                transitions.push(t);
+                stack.push((
+                    pos + step,
+                    loop_id,
+                    tr.to_state,
+                    new_capturer,
+                    zero_width_states,
+                ));
             }
         }
 
-        transitions
+        None
     }
 
-    fn get_loop_start_transitions(&self) -> HashSet<(u64, u64)> {
+    fn get_available_transitions(&self, start_state: u64) -> Vec<&Transition> {
+        match self.state_transitions.get(&start_state) {
+            Some(indices) => indices.iter().map(|i| &self.transitions[*i]).collect(),
+            None => vec![],
+        }
+    }
+
+    fn get_loop_start_transitions(transitions: &[Transition]) -> HashSet<(u64, u64)> {
         let mut loop_start_states = HashSet::new();
-        for tr in &self.transitions {
+        for tr in transitions {
             if tr.max_use.is_some() {
                 loop_start_states.insert(tr.from_state);
             }
         }
 
         let mut loop_start_transitions = HashSet::new();
-        for tr in &self.transitions {
+        for tr in transitions {
             if tr.max_use.is_none() && loop_start_states.contains(&tr.to_state) {
                 loop_start_transitions.insert((tr.from_state, tr.to_state));
             }
@@ -144,7 +204,11 @@ impl Evaluator {
 
 #[cfg(test)]
 mod test {
-    use crate::{common::str_to_tokens, evaluator::Evaluator, parser::Parser};
+    use crate::{
+        common::str_to_tokens,
+        evaluator::Evaluator,
+        parser::{Dialect, Flags, Parser},
+    };
 
     #[test]
     fn test_match() {
@@ -156,6 +220,9 @@ mod test {
 
         assert!(eval_match("ab{3}a", "abbba"));
         assert!(!eval_match("ab{4}a", "abbba"));
+        // Multi-digit counts.
+        assert!(eval_match("^a{12}$", "aaaaaaaaaaaa"));
+        assert!(!eval_match("^a{12}$", "aaaaaaaaaaa"));
 
         assert!(eval_match("(aab|aa)[cb]{2,}", "aabb"));
         assert!(eval_match("(aab|aa)[cb]{2,}", "aabc"));
@@ -181,6 +248,69 @@ mod test {
         assert!(!eval_match("^x{2}$", "x"));
         assert!(!eval_match("^x{2}$", "xxx"));
         assert!(eval_match("^x{2,4}$", "xxx"));
+
+        assert!(eval_match("cat|dog", "hotdog"));
+        assert!(eval_match("cat|dog", "cats"));
+        assert!(!eval_match("cat|dog", "cow"));
+        assert!(eval_match("^cat|dog$", "cat food"));
+        assert!(!eval_match("^cat|dog$", "dog food"));
+        assert!(eval_match("(a|b)x|(c)\\2", "cc"));
+        assert!(eval_match("(a)(b)(c)(d)(e)(f)(g)(h)(i)\\9", "abcdefghii"));
+        assert!(!eval_match("(a)(b)(c)(d)(e)(f)(g)(h)(i)\\9", "abcdefghia"));
+
+        assert!(eval_match("(?:ab)+c", "ababc"));
+        assert!(!eval_match("(?:ab)+c", "abac"));
+        assert!(eval_match("(?:a|b)(c)\\1", "bcc"));
+        assert!(!eval_match("(?:a|b)(c)\\1", "bcb"));
+        assert!(eval_match("(?:x(y))\\1", "xyy"));
+
+        assert!(eval_match("(?<word>ab|cd)-\\k<word>", "cd-cd"));
+        assert!(!eval_match("(?<word>ab|cd)-\\k<word>", "cd-ab"));
+        assert!(eval_match("(?P<a>x)(?<b>y)\\2\\k<a>", "xyyx"));
+        assert!(!eval_match("(?P<a>x)(?<b>y)\\2\\k<a>", "xyyy"));
+        assert!(eval_match("(?<a>[0-9])\\k<a>{2}", "777"));
+    }
+
+    #[test]
+    fn test_match_ignore_case() {
+        let flags = Flags {
+            ignore_case: true,
+            ..Flags::default()
+        };
+
+        assert!(eval_match_with_flags("hello", "HeLLo world", flags));
+        assert!(eval_match_with_flags("^[a-c]+$", "AbC", flags));
+        assert!(eval_match_with_flags("^[^a-c]+$", "xyz", flags));
+        assert!(!eval_match_with_flags("^[^a-c]+$", "xBz", flags));
+        assert!(eval_match_with_flags("(ab) \\1", "ab AB", flags));
+        assert!(!eval_match("(ab) \\1", "ab AB"));
+
+        assert!(eval_match("(?i)hello", "HELLO"));
+        assert!(eval_match("a(?i)b", "aB"));
+        assert!(!eval_match("a(?i)b", "AB"));
+        assert!(!eval_match("((?i)a)b", "AB"));
+        assert!(eval_match_with_flags("(?-i)a", "a", flags));
+        assert!(!eval_match_with_flags("(?-i)a", "A", flags));
+    }
+
+    #[test]
+    fn test_match_basic_dialect() {
+        assert!(eval_match_basic("a+?", "a+?"));
+        assert!(!eval_match_basic("a+", "aa"));
+        assert!(eval_match_basic("a\\+b", "aaab"));
+        assert!(eval_match_basic("ab\\?c", "ac"));
+        assert!(eval_match_basic("\\(ab\\)\\{2\\}", "abab"));
+        assert!(!eval_match_basic("\\(ab\\)\\{2\\}", "ab"));
+        assert!(eval_match_basic("a\\{2,\\}", "aaa"));
+        assert!(eval_match_basic("(a|b){2}", "(a|b){2}"));
+        assert!(eval_match_basic("cat\\|dog", "hotdog"));
+        assert!(eval_match_basic("\\(a\\)x\\1", "axa"));
+        // `^` and `$` are anchors only at the ends of an alternative.
+        assert!(eval_match_basic("a^b$c", "a^b$c"));
+        assert!(eval_match_basic("^a$", "a"));
+        assert!(!eval_match_basic("^a$", "ba"));
+        assert!(eval_match_basic("^*a", "*a"));
+        assert!(eval_match_basic("\\(^a$\\)", "a"));
     }
 
     fn eval_match(pattern: &str, subject: &str) -> bool {
@@ -188,4 +318,16 @@ mod test {
         let e = Evaluator::new(ast.generate());
         e.is_match(&str_to_tokens(subject)[..]).is_match()
     }
+
+    fn eval_match_with_flags(pattern: &str, subject: &str, flags: Flags) -> bool {
+        let ast = Parser::parse_regex_str_with(pattern, Dialect::Extended, flags).unwrap();
+        let e = Evaluator::new(ast.generate());
+        e.is_match(&str_to_tokens(subject)[..]).is_match()
+    }
+
+    fn eval_match_basic(pattern: &str, subject: &str) -> bool {
+        let ast = Parser::parse_regex_str_with(pattern, Dialect::Basic, Flags::default()).unwrap();
+        let e = Evaluator::new(ast.generate());
+        e.is_match(&str_to_tokens(subject)[..]).is_match()
+    }
 }
@@ -0,0 +1,291 @@
+use std::{
+    collections::VecDeque,
+    fs::File,
+    io::{self, BufRead, BufReader},
+};
+
+const STDIN_NAME: &str = "(standard input)";
+
+/// Reports an input that cannot be (fully) read, the search goes on with the next one.
+fn report_error(name: &str, err: impl std::fmt::Display) {
+    eprintln!("grep: {}: {}", name, err);
+}
+
+/// A line of input with its position within the file it was read from. With `-z` a line is a
+/// NUL-terminated record, which may contain newlines.
+pub(crate) struct InputLine {
+    pub(crate) text: String,
+    pub(crate) source: Option<String>,
+    // 1-based.
+    pub(crate) line_number: usize,
+    // Offset of the first byte of the line.
+    pub(crate) byte_offset: usize,
+    // Offsets of the U+FFFD chars in `text` standing for invalid UTF-8, along with the number of
+    // bytes they replaced.
+    replacements: Vec<(usize, usize)>,
+}
+
+impl InputLine {
+    /// Offset within the line as read from the input of an offset of `text`.
+    pub(crate) fn raw_offset(&self, offset: usize) -> usize {
+        self.replacements
+            .iter()
+            .take_while(|(replacement_offset, _)| *replacement_offset < offset)
+            .fold(offset, |raw_offset, (_, len)| {
+                raw_offset + len - char::REPLACEMENT_CHARACTER.len_utf8()
+            })
+    }
+}
+
+/// Decodes the line like `String::from_utf8_lossy`, also returning the offsets of the replacement
+/// chars with the length of the invalid sequences.
+fn decode_lossy(bytes: &[u8]) -> (String, Vec<(usize, usize)>) {
+    let mut text = String::with_capacity(bytes.len());
+    let mut replacements = vec![];
+
+    for chunk in bytes.utf8_chunks() {
+        text.push_str(chunk.valid());
+        if !chunk.invalid().is_empty() {
+            replacements.push((text.len(), chunk.invalid().len()));
+            text.push(char::REPLACEMENT_CHARACTER);
+        }
+    }
+
+    (text, replacements)
+}
+
+/// A single input (a file or STDIN), iterating its lines.
+pub(crate) struct InputFile {
+    // `None` for STDIN.
+    path: Option<String>,
+    should_return_path: bool,
+    reader: Box<dyn BufRead>,
+    separator: u8,
+    line_number: usize,
+    byte_offset: usize,
+    has_error: bool,
+}
+
+impl InputFile {
+    fn new(
+        path: Option<String>,
+        should_return_path: bool,
+        reader: Box<dyn BufRead>,
+        separator: u8,
+    ) -> Self {
+        Self {
+            path,
+            should_return_path,
+            reader,
+            separator,
+            line_number: 0,
+            byte_offset: 0,
+            has_error: false,
+        }
+    }
+
+    /// Name used in file listings and counts.
+    pub(crate) fn name(&self) -> &str {
+        self.path.as_deref().unwrap_or(STDIN_NAME)
+    }
+
+    /// Whether output lines of this input are prefixed with its name.
+    pub(crate) fn should_return_path(&self) -> bool {
+        self.should_return_path
+    }
+
+    /// Whether reading stopped early because of a read error.
+    pub(crate) fn has_error(&self) -> bool {
+        self.has_error
+    }
+}
+
+/// Removes the `\n` or `\r\n` line ending, or the given record separator.
+fn trim_line_ending(line: &str, separator: u8) -> &str {
+    if separator != b'\n' {
+        return line.strip_suffix(separator as char).unwrap_or(line);
+    }
+
+    let line = line.strip_suffix('\n').unwrap_or(line);
+    line.strip_suffix('\r').unwrap_or(line)
+}
+
+impl Iterator for InputFile {
+    type Item = InputLine;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut input_line = vec![];
+
+        match self.reader.read_until(self.separator, &mut input_line) {
+            Ok(0) => None,
+            Ok(len) => {
+                // Invalid UTF-8 sequences become U+FFFD, the rest of the line is still searched.
+                let (input_line, replacements) = decode_lossy(&input_line);
+
+                self.line_number += 1;
+                let line_byte_offset = self.byte_offset;
+                self.byte_offset += len;
+
+                Some(InputLine {
+                    text: trim_line_ending(&input_line, self.separator).to_string(),
+                    source: if self.should_return_path {
+                        self.path.clone()
+                    } else {
+                        None
+                    },
+                    line_number: self.line_number,
+                    byte_offset: line_byte_offset,
+                    replacements,
+                })
+            }
+            Err(err) => {
+                report_error(self.name(), err);
+                self.has_error = true;
+                None
+            }
+        }
+    }
+}
+
+/// Iterates the inputs one by one, so consumers can act on file boundaries.
+pub(crate) enum InputIterator {
+    Stdin {
+        is_consumed: bool,
+        separator: u8,
+    },
+    Files {
+        file_names: VecDeque<String>,
+        should_return_current_file_path: bool,
+        separator: u8,
+        // Set when a file or directory could not be opened.
+        has_error: bool,
+    },
+}
+
+impl InputIterator {
+    pub(crate) fn new_from_stdin(separator: u8) -> Self {
+        Self::Stdin {
+            is_consumed: false,
+            separator,
+        }
+    }
+
+    pub(crate) fn new_from_files(file_names: Vec<String>, separator: u8) -> Self {
+        Self::Files {
+            file_names: file_names.clone().into(),
+            should_return_current_file_path: file_names.len() > 1,
+            separator,
+            has_error: false,
+        }
+    }
+
+    /// Searches the working directory, like `-r` without files. The paths are relative to it,
+    /// without the `./` prefix.
+    pub(crate) fn new_from_current_directory(separator: u8) -> Self {
+        let mut input_iterator = Self::new_from_directories(&[".".to_string()], separator);
+        if let Self::Files { file_names, .. } = &mut input_iterator {
+            for file_name in file_names.iter_mut() {
+                if let Some(relative) = file_name.strip_prefix("./") {
+                    *file_name = relative.to_string();
+                }
+            }
+        }
+        input_iterator
+    }
+
+    pub(crate) fn new_from_directories(dir_names: &[String], separator: u8) -> Self {
+        let mut file_names = VecDeque::new();
+        let mut has_error = false;
+        let mut dir_stack = dir_names.to_vec();
+
+        while let Some(dir) = dir_stack.pop() {
+            match std::fs::read_dir(&dir) {
+                Ok(entries) => {
+                    for entry in entries.flatten() {
+                        let path = entry.path().to_string_lossy().to_string();
+                        match entry.metadata() {
+                            Ok(metadata) if metadata.is_file() => file_names.push_back(path),
+                            Ok(metadata) if metadata.is_dir() => dir_stack.push(path),
+                            Ok(_) => {}
+                            Err(err) => {
+                                report_error(&path, err);
+                                has_error = true;
+                            }
+                        }
+                    }
+                }
+                Err(err) => {
+                    report_error(&dir, err);
+                    has_error = true;
+                }
+            }
+        }
+
+        let should_return_current_file_path = file_names.len() > 1;
+
+        Self::Files {
+            file_names,
+            should_return_current_file_path,
+            separator,
+            has_error,
+        }
+    }
+
+    /// Whether a file or directory could not be opened.
+    pub(crate) fn has_error(&self) -> bool {
+        match self {
+            Self::Stdin { .. } => false,
+            Self::Files { has_error, .. } => *has_error,
+        }
+    }
+}
+
+impl Iterator for InputIterator {
+    type Item = InputFile;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self {
+            Self::Stdin {
+                is_consumed,
+                separator,
+            } => {
+                if *is_consumed {
+                    return None;
+                }
+
+                *is_consumed = true;
+                Some(InputFile::new(
+                    None,
+                    false,
+                    Box::new(io::stdin().lock()),
+                    *separator,
+                ))
+            }
+            Self::Files {
+                file_names,
+                should_return_current_file_path,
+                separator,
+                has_error,
+            } => {
+                while let Some(file_name) = file_names.pop_front() {
+                    match File::open(&file_name) {
+                        Ok(file) => {
+                            return Some(InputFile::new(
+                                Some(file_name),
+                                *should_return_current_file_path,
+                                Box::new(BufReader::new(file)),
+                                *separator,
+                            ));
+                        }
+                        Err(err) => {
+                            report_error(&file_name, err);
+                            *has_error = true;
+                        }
+                    }
+                }
+
+                None
+            }
+        }
+    }
+}
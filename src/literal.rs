@@ -0,0 +1,225 @@
+use std::collections::{HashMap, VecDeque};
+
+/// Case folding that keeps a char a single char, so positions stay the same after folding.
+fn fold_char(c: char) -> char {
+    let mut lower = c.to_lowercase();
+    match (lower.next(), lower.next()) {
+        (Some(folded), None) => folded,
+        _ => c,
+    }
+}
+
+/// Searches fixed strings without going through the regex parser and evaluator. A single literal
+/// uses Boyer-Moore-Horspool, multiple literals are searched at once with Aho-Corasick.
+pub(crate) enum LiteralMatcher {
+    Single(Horspool),
+    Multi(AhoCorasick),
+}
+
+impl LiteralMatcher {
+    pub(crate) fn new(literals: &[String], ignore_case: bool) -> Self {
+        let literals = literals
+            .iter()
+            .map(|literal| {
+                literal
+                    .chars()
+                    .map(|c| if ignore_case { fold_char(c) } else { c })
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+
+        if literals.len() == 1 {
+            Self::Single(Horspool::new(
+                literals.into_iter().next().unwrap(),
+                ignore_case,
+            ))
+        } else {
+            Self::Multi(AhoCorasick::new(&literals, ignore_case))
+        }
+    }
+
+    /// Every occurrence of every literal as char ranges, overlapping ones included.
+    pub(crate) fn occurrences(&self, haystack: &[char]) -> Vec<(usize, usize)> {
+        match self {
+            Self::Single(searcher) => searcher.occurrences(haystack),
+            Self::Multi(searcher) => searcher.occurrences(haystack),
+        }
+    }
+}
+
+pub(crate) struct Horspool {
+    needle: Vec<char>,
+    // Distance from the last occurrence of a char (before the needle's last char) to the end.
+    shift_table: HashMap<char, usize>,
+    ignore_case: bool,
+}
+
+impl Horspool {
+    fn new(needle: Vec<char>, ignore_case: bool) -> Self {
+        let mut shift_table = HashMap::new();
+
+        if !needle.is_empty() {
+            for (i, c) in needle[..needle.len() - 1].iter().enumerate() {
+                shift_table.insert(*c, needle.len() - 1 - i);
+            }
+        }
+
+        Self {
+            needle,
+            shift_table,
+            ignore_case,
+        }
+    }
+
+    fn occurrences(&self, haystack: &[char]) -> Vec<(usize, usize)> {
+        let needle_len = self.needle.len();
+        let fold = |c: char| if self.ignore_case { fold_char(c) } else { c };
+        let mut out = vec![];
+
+        if needle_len == 0 {
+            return (0..=haystack.len()).map(|i| (i, i)).collect();
+        }
+
+        let mut start = 0;
+        while start + needle_len <= haystack.len() {
+            let last = fold(haystack[start + needle_len - 1]);
+
+            if last == self.needle[needle_len - 1]
+                && self.needle[..needle_len - 1]
+                    .iter()
+                    .zip(&haystack[start..])
+                    .all(|(needle_c, haystack_c)| *needle_c == fold(*haystack_c))
+            {
+                out.push((start, start + needle_len));
+                // Overlapping occurrences are reported too.
+                start += 1;
+            } else {
+                start += self.shift_table.get(&last).unwrap_or(&needle_len);
+            }
+        }
+
+        out
+    }
+}
+
+pub(crate) struct AhoCorasick {
+    // Trie edges per node, node 0 is the root.
+    goto: Vec<HashMap<char, usize>>,
+    fail: Vec<usize>,
+    // Lengths of the literals ending at the node, including the ones reached by failure links.
+    output: Vec<Vec<usize>>,
+    ignore_case: bool,
+}
+
+impl AhoCorasick {
+    fn new(literals: &[Vec<char>], ignore_case: bool) -> Self {
+        let mut goto: Vec<HashMap<char, usize>> = vec![HashMap::new()];
+        let mut output: Vec<Vec<usize>> = vec![vec![]];
+
+        for literal in literals {
+            let mut node = 0;
+            for c in literal {
+                node = match goto[node].get(c) {
+                    Some(next) => *next,
+                    None => {
+                        goto.push(HashMap::new());
+                        output.push(vec![]);
+                        let next = goto.len() - 1;
+                        goto[node].insert(*c, next);
+                        next
+                    }
+                };
+            }
+            output[node].push(literal.len());
+        }
+
+        // Failure links in breadth-first order, so the parent's link is always ready.
+        let mut fail = vec![0; goto.len()];
+        let mut queue = goto[0].values().copied().collect::<VecDeque<_>>();
+
+        while let Some(node) = queue.pop_front() {
+            let edges = goto[node]
+                .iter()
+                .map(|(c, next)| (*c, *next))
+                .collect::<Vec<_>>();
+
+            for (c, next) in edges {
+                let mut fallback = fail[node];
+                while fallback != 0 && !goto[fallback].contains_key(&c) {
+                    fallback = fail[fallback];
+                }
+
+                fail[next] = goto[fallback].get(&c).copied().unwrap_or(0);
+                let mut inherited = output[fail[next]].clone();
+                output[next].append(&mut inherited);
+                queue.push_back(next);
+            }
+        }
+
+        Self {
+            goto,
+            fail,
+            output,
+            ignore_case,
+        }
+    }
+
+    fn occurrences(&self, haystack: &[char]) -> Vec<(usize, usize)> {
+        let mut out = vec![];
+        let mut node = 0;
+
+        // Empty literals match at the start too.
+        for len in &self.output[0] {
+            out.push((0, *len));
+        }
+
+        for (i, c) in haystack.iter().enumerate() {
+            let c = if self.ignore_case { fold_char(*c) } else { *c };
+
+            while node != 0 && !self.goto[node].contains_key(&c) {
+                node = self.fail[node];
+            }
+            node = self.goto[node].get(&c).copied().unwrap_or(0);
+
+            for len in &self.output[node] {
+                out.push((i + 1 - len, i + 1));
+            }
+        }
+
+        out.sort();
+        out
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::literal::LiteralMatcher;
+
+    fn occurrences(literals: &[&str], ignore_case: bool, haystack: &str) -> Vec<(usize, usize)> {
+        let literals = literals.iter().map(|l| l.to_string()).collect::<Vec<_>>();
+        let haystack = haystack.chars().collect::<Vec<_>>();
+        LiteralMatcher::new(&literals, ignore_case).occurrences(&haystack)
+    }
+
+    #[test]
+    fn test_horspool() {
+        assert_eq!(vec![(2, 5)], occurrences(&["cat"], false, "a cat"));
+        assert_eq!(vec![(0, 2), (1, 3)], occurrences(&["aa"], false, "aaa"));
+        assert_eq!(vec![(1, 4)], occurrences(&["a.c"], false, "xa.cabc"));
+        assert_eq!(vec![(0, 3)], occurrences(&["CaT"], true, "cAt"));
+        assert!(occurrences(&["cat"], false, "ca").is_empty());
+    }
+
+    #[test]
+    fn test_aho_corasick() {
+        assert_eq!(
+            vec![(0, 2), (0, 4), (1, 4), (2, 4)],
+            occurrences(&["he", "hers", "ers", "rs"], false, "hers")
+        );
+        assert_eq!(
+            vec![(2, 5), (6, 9)],
+            occurrences(&["cat", "dog"], false, "a cat dog")
+        );
+        assert_eq!(vec![(0, 3)], occurrences(&["xyz", "DOG"], true, "dog"));
+    }
+}
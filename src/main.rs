@@ -1,16 +1,18 @@
+use crate::common::EXIT_CODE_ERROR;
 use crate::common::EXIT_CODE_NO_MATCH;
 use crate::common::EXIT_CODE_SUCCESS;
-use crate::common::merge_overlapping_match_ranges;
-use crate::common::range_end_adjust;
-use crate::common::range_start_adjust;
-use crate::common::str_to_tokens;
 use crate::evaluator::EvalMatchResult;
-use crate::evaluator::Evaluator;
+use crate::output::OutputOptions;
+use crate::output::Printer;
+use crate::parser::Dialect;
+use crate::parser::Flags;
+use crate::program::InvalidPattern;
+use crate::program::Program;
+use crate::program::ProgramOptions;
 use clap::{Parser, ValueEnum};
+use input::InputIterator;
 use isatty::stdout_isatty;
-use log::error;
 use log::info;
-use std::collections::VecDeque;
 use std::fs::read_to_string;
 use std::io;
 use std::process;
@@ -19,11 +21,19 @@ mod ast;
 mod capturer;
 mod common;
 mod cond;
+mod error;
 mod evaluator;
+mod input;
+mod literal;
+mod output;
 mod parser;
+mod program;
 mod reader;
 mod token;
 mod transition;
+mod unicode;
+#[rustfmt::skip]
+mod unicode_tables;
 
 #[derive(Clone, Debug, ValueEnum, PartialEq)]
 enum ColorArg {
@@ -35,19 +45,110 @@ enum ColorArg {
 #[derive(clap::Parser)]
 #[command(version, about, long_about = None)]
 struct ProgramArgs {
-    filepath: Option<Vec<String>>,
-
-    #[arg(short = 'E')]
-    pattern: String,
+    /// The pattern (unless given by -e or -f), followed by the files to search.
+    #[arg(value_name = "PATTERN | FILE")]
+    positional: Vec<String>,
+
+    /// Interpret patterns as extended regular expressions (ERE).
+    #[arg(
+        short = 'E',
+        long = "extended-regexp",
+        default_value = "false",
+        overrides_with = "basic_regexp"
+    )]
+    extended_regexp: bool,
+
+    /// Interpret patterns as basic regular expressions (BRE), the default.
+    #[arg(
+        short = 'G',
+        long = "basic-regexp",
+        default_value = "false",
+        overrides_with = "extended_regexp"
+    )]
+    basic_regexp: bool,
+
+    /// Use PATTERN for matching, can be given multiple times.
+    #[arg(short = 'e', long = "regexp", value_name = "PATTERN")]
+    regexp: Vec<String>,
+
+    /// Read patterns from FILE, one per line.
+    #[arg(short = 'f', long = "file", value_name = "FILE")]
+    pattern_files: Vec<String>,
 
     #[arg(short = 'o', default_value = "false")]
     only_match: bool,
 
+    #[arg(short = 'i', long = "ignore-case", default_value = "false")]
+    ignore_case: bool,
+
+    /// Make \w, \d, \s, the word boundaries and -w match by Unicode properties instead of ASCII.
+    #[arg(long, default_value = "false")]
+    unicode: bool,
+
+    /// Interpret patterns as fixed strings.
+    #[arg(short = 'F', long = "fixed-strings", default_value = "false")]
+    fixed_strings: bool,
+
+    /// Select only matches not surrounded by word chars.
+    #[arg(short = 'w', long = "word-regexp", default_value = "false")]
+    word_regexp: bool,
+
+    /// Select only matches of the whole line.
+    #[arg(short = 'x', long = "line-regexp", default_value = "false")]
+    line_regexp: bool,
+
+    #[arg(short = 'v', long = "invert-match", default_value = "false")]
+    invert_match: bool,
+
+    #[arg(short = 'n', long = "line-number", default_value = "false")]
+    line_number: bool,
+
+    #[arg(short = 'b', long = "byte-offset", default_value = "false")]
+    byte_offset: bool,
+
+    /// Print the 1-based column of the first match.
+    #[arg(long, default_value = "false")]
+    column: bool,
+
+    #[arg(short = 'A', long = "after-context", value_name = "NUM")]
+    after_context: Option<usize>,
+
+    #[arg(short = 'B', long = "before-context", value_name = "NUM")]
+    before_context: Option<usize>,
+
+    #[arg(short = 'C', long = "context", value_name = "NUM")]
+    context: Option<usize>,
+
+    /// Print only the number of selected lines per file.
+    #[arg(short = 'c', long = "count", default_value = "false")]
+    count: bool,
+
+    /// Print only the names of files with selected lines.
+    #[arg(short = 'l', long = "files-with-matches", default_value = "false")]
+    files_with_matches: bool,
+
+    /// Print only the names of files without selected lines.
+    #[arg(short = 'L', long = "files-without-match", default_value = "false")]
+    files_without_match: bool,
+
+    /// Print nothing, exit with success at the first selected line.
+    #[arg(short = 'q', long = "quiet", alias = "silent", default_value = "false")]
+    quiet: bool,
+
+    /// Stop reading a file after NUM selected lines.
+    #[arg(short = 'm', long = "max-count", value_name = "NUM")]
+    max_count: Option<usize>,
+
     #[arg(long, default_value = "never")]
     color: ColorArg,
 
+    /// Search the files of the given directories recursively, the working directory without any.
     #[arg(short, default_value = "false")]
     recursive: bool,
+
+    /// Input and output records are terminated by NUL instead of newline.
+    #[arg(short = 'z', long = "null-data", default_value = "false")]
+    null_data: bool,
 }
 
 impl ProgramArgs {
@@ -59,133 +160,83 @@ impl ProgramArgs {
         }
     }
 
-    fn input_iterator(&self) -> InputIterator {
-        if self.recursive {
-            InputIterator::new_from_directories(
-                &self
-                    .filepath
-                    .as_ref()
-                    .expect("missing files in recursive mode"),
-            )
-        } else if let Some(files) = self.filepath.as_ref() {
-            InputIterator::new_from_files(files.clone())
-        } else {
-            InputIterator::new_from_stdin()
-        }
+    /// Without `-e` and `-f` the first positional argument is the pattern.
+    fn has_positional_pattern(&self) -> bool {
+        self.regexp.is_empty() && self.pattern_files.is_empty()
     }
-}
 
-enum InputIterator {
-    Stdin,
-    Files {
-        file_names: VecDeque<String>,
-        active_file_lines: VecDeque<String>,
-        current_file_path: Option<String>,
-        should_return_current_file_path: bool,
-    },
-}
+    /// All patterns of the positional argument, `-e` and `-f`, a line is selected if any of them
+    /// matches.
+    fn patterns(&self) -> io::Result<Vec<String>> {
+        let mut patterns = if self.has_positional_pattern() {
+            self.positional.iter().take(1).cloned().collect::<Vec<_>>()
+        } else {
+            vec![]
+        };
+        patterns.extend(self.regexp.iter().cloned());
+
+        for pattern_file in &self.pattern_files {
+            let content = read_to_string(pattern_file)?;
+            patterns.extend(content.lines().map(|line| line.to_string()));
+        }
 
-impl InputIterator {
-    fn new_from_stdin() -> Self {
-        Self::Stdin
+        Ok(patterns)
     }
 
-    fn new_from_files(file_names: Vec<String>) -> Self {
-        Self::Files {
-            file_names: file_names.clone().into(),
-            active_file_lines: VecDeque::new(),
-            current_file_path: None,
-            should_return_current_file_path: file_names.len() > 1,
+    fn program_options(&self) -> ProgramOptions {
+        ProgramOptions {
+            dialect: if self.extended_regexp {
+                Dialect::Extended
+            } else {
+                Dialect::Basic
+            },
+            flags: Flags {
+                ignore_case: self.ignore_case,
+                unicode: self.unicode,
+                ..Flags::default()
+            },
+            line_regexp: self.line_regexp,
+            word_regexp: self.word_regexp,
+            fixed_strings: self.fixed_strings,
         }
     }
 
-    fn new_from_directories(dir_names: &Vec<String>) -> Self {
-        let mut file_names = VecDeque::new();
-        let mut dir_stack = dir_names.clone();
-
-        while let Some(dir) = dir_stack.pop() {
-            if let Ok(entries) = std::fs::read_dir(&dir) {
-                for entry in entries.flatten() {
-                    if let Ok(metadata) = entry.metadata() {
-                        if metadata.is_file() {
-                            file_names.push_back(entry.path().to_string_lossy().to_string());
-                        } else if metadata.is_dir() {
-                            dir_stack.push(entry.path().to_string_lossy().to_string());
-                        }
-                    } else {
-                        error!("Error: cannot read metadata for dir entry: {:?}", entry);
-                    }
-                }
-            } else {
-                error!("Error: cannot read dir: {}", dir);
-            }
+    fn output_options(&self) -> OutputOptions {
+        OutputOptions {
+            only_match: self.only_match,
+            color: self.is_color(),
+            line_number: self.line_number,
+            byte_offset: self.byte_offset,
+            column: self.column,
+            before_context: self.before_context.or(self.context),
+            after_context: self.after_context.or(self.context),
+            record_terminator: self.record_separator() as char,
         }
+    }
 
-        let should_return_current_file_path = file_names.len() > 1;
+    fn record_separator(&self) -> u8 {
+        if self.null_data { b'\0' } else { b'\n' }
+    }
 
-        Self::Files {
-            file_names,
-            active_file_lines: VecDeque::new(),
-            current_file_path: None,
-            should_return_current_file_path,
+    fn file_paths(&self) -> &[String] {
+        if self.has_positional_pattern() && !self.positional.is_empty() {
+            &self.positional[1..]
+        } else {
+            &self.positional
         }
     }
-}
-
-impl Iterator for InputIterator {
-    type Item = (String, Option<String>);
-
-    fn next(&mut self) -> Option<Self::Item> {
-        match self {
-            Self::Stdin => {
-                let mut input_line = String::new();
-                match io::stdin().read_line(&mut input_line) {
-                    Ok(0) => None,
-                    Ok(_) => Some((input_line.trim_end().to_string(), None)),
-                    _ => {
-                        error!("Failed reading STDIN");
-                        None
-                    }
-                }
-            }
-            Self::Files {
-                file_names,
-                active_file_lines,
-                current_file_path,
-                should_return_current_file_path,
-            } => {
-                if active_file_lines.is_empty() {
-                    loop {
-                        if file_names.is_empty() {
-                            return None;
-                        }
-
-                        let file_name = file_names.pop_front().unwrap();
-                        *current_file_path = Some(file_name.clone());
-                        let content = read_to_string(file_name).unwrap();
-                        *active_file_lines = content
-                            .lines()
-                            .map(|l| l.to_string())
-                            .collect::<VecDeque<_>>();
-
-                        if !active_file_lines.is_empty() {
-                            break;
-                        }
-                    }
-                }
 
-                match active_file_lines.pop_front() {
-                    Some(line) => Some((
-                        line,
-                        if *should_return_current_file_path {
-                            current_file_path.clone()
-                        } else {
-                            None
-                        },
-                    )),
-                    None => None,
-                }
-            }
+    fn input_iterator(&self) -> InputIterator {
+        let files = self.file_paths();
+
+        if self.recursive && files.is_empty() {
+            InputIterator::new_from_current_directory(self.record_separator())
+        } else if self.recursive {
+            InputIterator::new_from_directories(files, self.record_separator())
+        } else if !files.is_empty() {
+            InputIterator::new_from_files(files.to_vec(), self.record_separator())
+        } else {
+            InputIterator::new_from_stdin(self.record_separator())
         }
     }
 }
@@ -197,69 +248,101 @@ fn main() {
     info!("Peter Grep Starts");
 
     let args = ProgramArgs::parse();
+    if args.has_positional_pattern() && args.positional.is_empty() {
+        eprintln!("Error: no pattern given");
+        process::exit(EXIT_CODE_ERROR);
+    }
+    let patterns = match args.patterns() {
+        Ok(patterns) => patterns,
+        Err(err) => {
+            eprintln!("Error: cannot read pattern file: {}", err);
+            process::exit(EXIT_CODE_ERROR);
+        }
+    };
+    let program = match Program::compile(&patterns, args.program_options()) {
+        Ok(program) => program,
+        Err(InvalidPattern { pattern, error }) => {
+            eprintln!("Error: invalid pattern: {}", error);
+            eprintln!("{}", error.caret_diagnostic(pattern));
+            process::exit(EXIT_CODE_ERROR);
+        }
+    };
+
     let mut has_match = false;
-    let input_it = args.input_iterator();
+    let mut has_error = false;
+    let mut printer = Printer::new(args.output_options());
+    let is_listing_files = args.files_with_matches || args.files_without_match;
+
+    let mut input_iterator = args.input_iterator();
 
-    for (line, source) in input_it {
-        let ast_root = crate::parser::Parser::parse_regex_str(&args.pattern).unwrap();
-        let evaluator = Evaluator::new(ast_root.generate());
+    'files: for mut input_file in &mut input_iterator {
+        let mut selected_count = 0usize;
 
-        match evaluator.is_match(&str_to_tokens(&line)[..]) {
-            EvalMatchResult::Match { matches } => {
-                if let Some(source) = source {
-                    print!("{}:", source);
+        for input_line in &mut input_file {
+            if args.max_count.is_some_and(|max| selected_count >= max) {
+                // Only the trailing context of the last selected line is left to print.
+                if args.count || !printer.has_pending_after_context() {
+                    break;
                 }
 
-                if args.only_match {
-                    for (start, end) in matches {
-                        let start = range_start_adjust(start);
-                        let end = range_end_adjust(end, line.len());
-                        println!("{}", &line[start..end]);
-                    }
-                } else {
-                    if args.is_color() {
-                        let merged_ranges = merge_overlapping_match_ranges(&matches);
-
-                        let mut merge_iter = merged_ranges.iter();
-                        let mut previous_range = merge_iter.next().unwrap();
-
-                        print!("{}", &line[..range_start_adjust(previous_range.0)]);
-                        print!(
-                            "\x1B[01;31m{}\x1B[m",
-                            &line[range_start_adjust(previous_range.0)
-                                ..range_end_adjust(previous_range.1, line.len())]
-                        );
-
-                        for range in merge_iter {
-                            print!(
-                                "{}",
-                                &line[range_end_adjust(previous_range.1, line.len())
-                                    ..range_start_adjust(range.0)]
-                            );
-                            print!(
-                                "\x1B[01;31m{}\x1B[m",
-                                &line[range_start_adjust(range.0)
-                                    ..range_end_adjust(range.1, line.len())]
-                            );
-
-                            previous_range = range;
-                        }
-
-                        print!(
-                            "{}\n",
-                            &line[range_end_adjust(previous_range.1, line.len())..]
-                        );
-                    } else {
-                        println!("{}", line);
-                    }
+                printer.rejected_line(input_line);
+                continue;
+            }
+
+            let matches = match program.is_match(&input_line.text) {
+                EvalMatchResult::Match { matches } => Some(matches),
+                EvalMatchResult::NoMatch => None,
+            };
+
+            // With inversion the selected lines are the ones without a match.
+            if matches.is_some() == args.invert_match {
+                if !is_listing_files && !args.count {
+                    printer.rejected_line(input_line);
                 }
+                continue;
+            }
+            selected_count += 1;
+
+            if args.quiet {
+                // The exit status is known, no more input is read.
                 has_match = true;
+                break 'files;
+            }
+
+            if is_listing_files {
+                // The first selected line decides the listing, the rest of the file is not read.
+                break;
+            }
+
+            if !args.count {
+                printer.selected_line(input_line, matches.as_deref());
+            }
+        }
+
+        printer.end_file();
+        has_error |= input_file.has_error();
+
+        if is_listing_files {
+            if (selected_count > 0) == args.files_with_matches && !args.quiet {
+                println!("{}", input_file.name());
+            }
+        } else if args.count && !args.quiet {
+            if input_file.should_return_path() {
+                print!("{}:", input_file.name());
             }
-            EvalMatchResult::NoMatch => {}
+            println!("{}", selected_count);
         }
+
+        // As of GNU grep 3.5 the exit status tells whether a line was selected, for `-L` too.
+        has_match |= selected_count > 0;
     }
 
-    if has_match {
+    has_error |= input_iterator.has_error();
+
+    // An unreadable input is an error even if other inputs matched, unless `-q` found a line.
+    if has_error && !(args.quiet && has_match) {
+        process::exit(EXIT_CODE_ERROR)
+    } else if has_match {
         process::exit(EXIT_CODE_SUCCESS)
     } else {
         process::exit(EXIT_CODE_NO_MATCH)
@@ -0,0 +1,218 @@
+use std::collections::VecDeque;
+
+use crate::{common::merge_overlapping_match_ranges, input::InputLine};
+
+const SEPARATOR_SELECTED: char = ':';
+const SEPARATOR_CONTEXT: char = '-';
+const GROUP_SEPARATOR: &str = "--";
+
+pub(crate) struct OutputOptions {
+    pub(crate) only_match: bool,
+    pub(crate) color: bool,
+    pub(crate) line_number: bool,
+    pub(crate) byte_offset: bool,
+    pub(crate) column: bool,
+    // `None` when not given, `Some(0)` still separates the groups with `--`.
+    pub(crate) before_context: Option<usize>,
+    pub(crate) after_context: Option<usize>,
+    // Printed after each line (or `-o` match), `\0` with `-z`.
+    pub(crate) record_terminator: char,
+}
+
+/// Prints selected lines and keeps track of the context lines around them.
+///
+/// Lines are fed in input order. Non-selected lines are kept in a ring buffer until a selected
+/// line shows up (before context), or printed right away while the countdown after the last
+/// selected line is running (after context). Non-adjacent groups are separated by `--`.
+pub(crate) struct Printer {
+    options: OutputOptions,
+    before_lines: VecDeque<InputLine>,
+    after_remaining: usize,
+    // Source and line number of the last printed line.
+    last_printed: Option<(Option<String>, usize)>,
+}
+
+impl Printer {
+    pub(crate) fn new(options: OutputOptions) -> Self {
+        Self {
+            options,
+            before_lines: VecDeque::new(),
+            after_remaining: 0,
+            last_printed: None,
+        }
+    }
+
+    fn has_context(&self) -> bool {
+        self.options.before_context.is_some() || self.options.after_context.is_some()
+    }
+
+    /// Prints a selected line. `matches` are the byte ranges to extract or highlight, `None` for
+    /// lines selected by an inverted search.
+    pub(crate) fn selected_line(
+        &mut self,
+        input_line: InputLine,
+        matches: Option<&[(usize, usize)]>,
+    ) {
+        if self.has_context() {
+            for context_line in std::mem::take(&mut self.before_lines) {
+                self.print_context_line(&context_line);
+            }
+            self.after_remaining = self.options.after_context.unwrap_or(0);
+        }
+
+        match matches {
+            Some(matches) => self.print_matching_line(&input_line, matches),
+            None if !self.options.only_match => {
+                self.print_group_separator(&input_line);
+                self.print_line_prefix(
+                    &input_line,
+                    None,
+                    input_line.byte_offset,
+                    SEPARATOR_SELECTED,
+                );
+                print!("{}{}", input_line.text, self.options.record_terminator);
+            }
+            None => {}
+        }
+    }
+
+    /// Handles a line that is not selected, it is only printed as context.
+    pub(crate) fn rejected_line(&mut self, input_line: InputLine) {
+        if !self.has_context() {
+            return;
+        }
+
+        if self.after_remaining > 0 {
+            self.after_remaining -= 1;
+            self.print_context_line(&input_line);
+            return;
+        }
+
+        let before_context = self.options.before_context.unwrap_or(0);
+        if before_context == 0 {
+            return;
+        }
+
+        if self.before_lines.len() == before_context {
+            self.before_lines.pop_front();
+        }
+        self.before_lines.push_back(input_line);
+    }
+
+    /// Whether lines after the last selected line are still printed as context.
+    pub(crate) fn has_pending_after_context(&self) -> bool {
+        self.has_context() && self.after_remaining > 0
+    }
+
+    /// Context never reaches over to the next file.
+    pub(crate) fn end_file(&mut self) {
+        self.before_lines.clear();
+        self.after_remaining = 0;
+    }
+
+    /// Context lines have nothing to show when only the matching parts are printed, but they
+    /// still join the groups the `--` separators are placed by.
+    fn print_context_line(&mut self, input_line: &InputLine) {
+        self.print_group_separator(input_line);
+        if self.options.only_match {
+            return;
+        }
+
+        self.print_line_prefix(input_line, None, input_line.byte_offset, SEPARATOR_CONTEXT);
+        print!("{}{}", input_line.text, self.options.record_terminator);
+    }
+
+    /// Prints `--` when the line does not directly follow the previous printed line, then records
+    /// the line as printed.
+    fn print_group_separator(&mut self, input_line: &InputLine) {
+        if let Some((source, line_number)) = &self.last_printed
+            && self.has_context()
+            && (source != &input_line.source || line_number + 1 != input_line.line_number)
+        {
+            println!("{}", GROUP_SEPARATOR);
+        }
+
+        self.last_printed = Some((input_line.source.clone(), input_line.line_number));
+    }
+
+    /// Prints the file name, line number, column and byte offset parts enabled by the options.
+    fn print_line_prefix(
+        &self,
+        input_line: &InputLine,
+        column: Option<usize>,
+        byte_offset: usize,
+        separator: char,
+    ) {
+        if let Some(source) = &input_line.source {
+            print!("{}{}", source, separator);
+        }
+
+        if self.options.line_number {
+            print!("{}{}", input_line.line_number, separator);
+        }
+
+        if self.options.column
+            && let Some(column) = column
+        {
+            print!("{}{}", column, separator);
+        }
+
+        if self.options.byte_offset {
+            print!("{}{}", byte_offset, separator);
+        }
+    }
+
+    fn print_matching_line(&mut self, input_line: &InputLine, matches: &[(usize, usize)]) {
+        let line = &input_line.text;
+        self.print_group_separator(input_line);
+
+        if self.options.only_match {
+            for (start, end) in matches {
+                self.print_line_prefix(
+                    input_line,
+                    Some(input_line.raw_offset(*start) + 1),
+                    input_line.byte_offset + input_line.raw_offset(*start),
+                    SEPARATOR_SELECTED,
+                );
+                print!("{}{}", &line[*start..*end], self.options.record_terminator);
+            }
+            return;
+        }
+
+        // The leftmost match is the first one found.
+        self.print_line_prefix(
+            input_line,
+            Some(input_line.raw_offset(matches[0].0) + 1),
+            input_line.byte_offset,
+            SEPARATOR_SELECTED,
+        );
+
+        if self.options.color {
+            let merged_ranges = merge_overlapping_match_ranges(matches);
+
+            let mut merge_iter = merged_ranges.iter();
+            let mut previous_range = merge_iter.next().unwrap();
+
+            print!("{}", &line[..previous_range.0]);
+            print!(
+                "\x1B[01;31m{}\x1B[m",
+                &line[previous_range.0..previous_range.1]
+            );
+
+            for range in merge_iter {
+                print!("{}", &line[previous_range.1..range.0]);
+                print!("\x1B[01;31m{}\x1B[m", &line[range.0..range.1]);
+
+                previous_range = range;
+            }
+
+            print!(
+                "{}{}",
+                &line[previous_range.1..],
+                self.options.record_terminator
+            );
+        } else {
+            print!("{}{}", line, self.options.record_terminator);
+        }
+    }
+}
@@ -1,224 +1,817 @@
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 
 use crate::{
     ast::AstNode,
-    capturer,
-    common::{Error, Incrementer},
-    cond::Literal,
+    common::{Incrementer, is_word_char},
+    cond::{Literal, PosixClass},
+    error::PatternError,
     reader::Reader,
+    unicode::{decimal_number_ranges, property_ranges},
 };
 
-pub(crate) struct Parser;
+/// Matching options that can be set from the command line and toggled inline with `(?...)`.
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct Flags {
+    /// `i`
+    pub(crate) ignore_case: bool,
+    /// `\w`, `\d`, `\s` and the word boundaries go by Unicode properties, command line only
+    /// (`--unicode`).
+    pub(crate) unicode: bool,
+    /// `^` and `$` match at the line breaks inside the subject too (`m`).
+    pub(crate) multi_line: bool,
+    /// `.` matches `\n` too (`s`).
+    pub(crate) dot_all: bool,
+    /// Whitespace and `#` comments in the pattern are ignored (`x`).
+    pub(crate) extended: bool,
+    /// Quantifiers are lazy, a `?` suffix makes them greedy (`U`).
+    pub(crate) swap_greed: bool,
+}
+
+/// Chars with a special meaning that BRE only recognizes when escaped, and ERE only when not.
+const DIALECT_META_CHARS: &str = "(){}|+?";
+
+/// Letters of the `\d`-like escapes, usable standalone and in bracket expressions.
+const SHORTHAND_CLASSES: &str = "dDwWsShv";
+
+/// POSIX regular expression syntax variants.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub(crate) enum Dialect {
+    /// BRE (`-G`): `\(`, `\)`, `\{`, `\}`, `\|`, `\+` and `\?` are special, the plain chars are
+    /// literals.
+    Basic,
+    /// ERE (`-E`).
+    #[default]
+    Extended,
+}
+
+/// What a parenthesized group does with its content, told by the prefix after `(?`.
+enum GroupKind {
+    Capturing(u64),
+    NonCapturing,
+    // `(?flags:...)`, non-capturing.
+    Flagged,
+    Atomic,
+    Lookahead { is_negated: bool },
+    Lookbehind { is_negated: bool },
+}
+
+pub(crate) struct Parser {
+    capture_id_provider: Incrementer,
+    // Ids of the named groups, `\k<name>` only refers to names defined before it.
+    capture_names: HashMap<String, u64>,
+    dialect: Dialect,
+    flags: Flags,
+}
 
 impl Parser {
-    pub(crate) fn parse_regex_str(s: &str) -> Result<AstNode, Error> {
-        Self::parse(&mut Reader::new(&s.chars().collect::<Vec<_>>()[..]))
+    #[allow(dead_code)]
+    pub(crate) fn parse_regex_str(s: &str) -> Result<AstNode, PatternError> {
+        Self::parse_regex_str_with(s, Dialect::Extended, Flags::default())
     }
 
-    fn parse(reader: &mut Reader<'_, char>) -> Result<AstNode, Error> {
-        let mut capture_group_id = Incrementer::new_from(1);
-        let seq_node = Self::parse_sequence(reader, &mut capture_group_id, |reader| {
-            reader.peek().is_none()
-        })?;
-        Ok(AstNode::Root(Box::new(seq_node)))
+    pub(crate) fn parse_regex_str_with(
+        s: &str,
+        dialect: Dialect,
+        flags: Flags,
+    ) -> Result<AstNode, PatternError> {
+        let mut parser = Self {
+            capture_id_provider: Incrementer::new_from(1),
+            capture_names: HashMap::new(),
+            dialect,
+            flags,
+        };
+        parser.parse(&mut Reader::new(&s.chars().collect::<Vec<_>>()[..]))
+    }
+
+    /// The meta char at the reader position, taking the dialect's escaping into account.
+    fn peek_meta(dialect: Dialect, reader: &Reader<'_, char>) -> Option<char> {
+        match dialect {
+            Dialect::Extended => reader
+                .peek()
+                .copied()
+                .filter(|c| DIALECT_META_CHARS.contains(*c)),
+            Dialect::Basic => match (reader.peek(), reader.peek_nth(1)) {
+                (Some('\\'), Some(c)) if DIALECT_META_CHARS.contains(*c) => Some(*c),
+                _ => None,
+            },
+        }
+    }
+
+    fn assert_pop_meta(
+        &self,
+        reader: &mut Reader<'_, char>,
+        expected: char,
+    ) -> Result<(), PatternError> {
+        if self.dialect == Dialect::Basic {
+            reader.assert_pop('\\')?;
+        }
+        reader.assert_pop(expected)?;
+        Ok(())
+    }
+
+    fn parse(&mut self, reader: &mut Reader<'_, char>) -> Result<AstNode, PatternError> {
+        let mut options = self.parse_alternation(reader)?;
+
+        if reader.peek().is_some() {
+            // The alternation only stops early on a `)` without an opening pair.
+            return Err(PatternError::UnmatchedCloseParen { pos: reader.pos() });
+        }
+
+        let node = if options.len() == 1 {
+            options.pop().unwrap()
+        } else {
+            // Top level alternation is not a capture group, `\1` still refers to the first `(`.
+            AstNode::Alt { options, id: None }
+        };
+
+        Ok(AstNode::Root(Box::new(node)))
+    }
+
+    /// Parses `|` separated sequences until the end of the input or a closing `)`.
+    fn parse_alternation(
+        &mut self,
+        reader: &mut Reader<'_, char>,
+    ) -> Result<Vec<AstNode>, PatternError> {
+        let mut options = vec![];
+
+        let dialect = self.dialect;
+
+        loop {
+            let alt = self.parse_sequence(reader, |r| {
+                r.peek().is_none() || matches!(Self::peek_meta(dialect, r), Some(')') | Some('|'))
+            })?;
+            options.push(alt);
+
+            match Self::peek_meta(dialect, reader) {
+                Some('|') => self.assert_pop_meta(reader, '|')?,
+                _ => break,
+            }
+        }
+
+        Ok(options)
     }
 
     fn parse_sequence<FnUntil>(
+        &mut self,
         reader: &mut Reader<'_, char>,
-        capture_group_id: &mut Incrementer,
         until_pred: FnUntil,
-    ) -> Result<AstNode, Error>
+    ) -> Result<AstNode, PatternError>
     where
         FnUntil: Fn(&mut Reader<'_, char>) -> bool,
     {
         let mut items = vec![];
 
         loop {
+            self.skip_extended_whitespace(reader);
             if until_pred(reader) {
                 break;
             }
 
-            items.push(Self::parse_unit(reader, capture_group_id)?);
+            let is_sequence_start = items.is_empty();
+            items.push(self.parse_unit(reader, is_sequence_start)?);
         }
 
         Ok(AstNode::Seq(items))
     }
 
-    fn parse_unit(
-        reader: &mut Reader<'_, char>,
-        capture_id_provider: &mut Incrementer,
-    ) -> Result<AstNode, Error> {
-        match reader.peek() {
-            Some(c) => match c {
-                '(' => {
-                    let capture_id = capture_id_provider.get();
-                    reader.assert_pop('(')?;
-                    let mut options = vec![];
-
-                    loop {
-                        let alt = Self::parse_sequence(reader, capture_id_provider, |r| {
-                            match r.peek() {
-                                Some(')') | None | Some('|') => true,
-                                _ => false,
-                            }
-                        })?;
-                        options.push(alt);
-
-                        match reader.peek() {
-                            Some(')') => break,
-                            Some('|') => {
-                                reader.assert_pop('|')?;
-                                continue;
-                            }
-                            other => {
-                                return Err(
-                                    format!("Invalid ending of paren group: {:?}", other).into()
-                                );
-                            }
-                        }
-                    }
+    fn parse_group(&mut self, reader: &mut Reader<'_, char>) -> Result<AstNode, PatternError> {
+        let open_pos = reader.pos();
+        self.assert_pop_meta(reader, '(')?;
+        // Inline flags set inside the group are only valid until its end.
+        let outer_flags = self.flags;
 
+        let kind = if self.dialect == Dialect::Extended
+            && let Some('?') = reader.peek()
+        {
+            let kind = match (reader.peek_nth(1), reader.peek_nth(2)) {
+                (Some(':'), _) => GroupKind::NonCapturing,
+                (Some('>'), _) => GroupKind::Atomic,
+                (Some('='), _) => GroupKind::Lookahead { is_negated: false },
+                (Some('!'), _) => GroupKind::Lookahead { is_negated: true },
+                (Some('<'), Some('=')) => GroupKind::Lookbehind { is_negated: false },
+                (Some('<'), Some('!')) => GroupKind::Lookbehind { is_negated: true },
+                (Some('<'), _) | (Some('P'), _) => {
+                    GroupKind::Capturing(self.parse_group_name_prefix(reader)?)
+                }
+                (Some('#'), _) => {
+                    reader.parse_while(|c| *c != ')');
+                    if reader.peek().is_none() {
+                        return Err(PatternError::UnmatchedOpenParen { pos: open_pos });
+                    }
                     reader.assert_pop(')')?;
+                    return Ok(AstNode::Seq(vec![]));
+                }
+                _ => {
+                    if !self.parse_flags(reader)? {
+                        // `(?flags)` lasts until the end of the enclosing group.
+                        return Ok(AstNode::Seq(vec![]));
+                    }
+                    GroupKind::Flagged
+                }
+            };
 
-                    Ok(Self::check_modifier(
-                        reader,
-                        AstNode::Alt {
-                            options,
-                            id: capture_id,
-                        },
-                    )?)
+            // Pops the `?` and the rest of the prefix, named groups and flags have parsed it
+            // already.
+            if !matches!(kind, GroupKind::Capturing(_) | GroupKind::Flagged) {
+                reader.assert_pop('?')?;
+                if let GroupKind::Lookbehind { .. } = kind {
+                    reader.assert_pop('<')?;
                 }
+                reader.pop()?;
+            }
+
+            kind
+        } else {
+            GroupKind::Capturing(self.capture_id_provider.get())
+        };
+
+        let options = self.parse_alternation(reader)?;
+        self.flags = outer_flags;
+
+        if reader.peek().is_none() {
+            return Err(PatternError::UnmatchedOpenParen { pos: open_pos });
+        }
+        self.assert_pop_meta(reader, ')')?;
+
+        let group = AstNode::Alt {
+            options,
+            // Non-capturing groups take no id, so they do not shift the numbering of `\N`
+            // references.
+            id: match kind {
+                GroupKind::Capturing(id) => Some(id),
+                _ => None,
+            },
+        };
+        let node = match kind {
+            GroupKind::Capturing(_) | GroupKind::NonCapturing | GroupKind::Flagged => group,
+            GroupKind::Atomic => AstNode::Atomic(Box::new(group)),
+            GroupKind::Lookahead { is_negated } => AstNode::Lookahead {
+                node: Box::new(group),
+                is_negated,
+            },
+            GroupKind::Lookbehind { is_negated } => AstNode::Lookbehind {
+                node: Box::new(group),
+                is_negated,
+            },
+        };
+
+        self.check_modifier(reader, node)
+    }
+
+    /// `is_sequence_start` tells if the unit is the first of its alternative, BRE only treats `^`
+    /// as an anchor there.
+    fn parse_unit(
+        &mut self,
+        reader: &mut Reader<'_, char>,
+        is_sequence_start: bool,
+    ) -> Result<AstNode, PatternError> {
+        if let Some('(') = Self::peek_meta(self.dialect, reader) {
+            return self.parse_group(reader);
+        }
+
+        match reader.peek() {
+            Some(c) => match c {
                 '[' => {
-                    reader.assert_pop('[')?;
-                    let is_negated = if let Some('^') = reader.peek() {
-                        reader.assert_pop('^')?;
-                        true
+                    let group = self.parse_bracket(reader)?;
+                    Ok(self.check_modifier(reader, group)?)
+                }
+                '^' if self.dialect == Dialect::Basic => {
+                    reader.pop()?;
+                    if is_sequence_start {
+                        // A `*` right after a leading `^` is a literal.
+                        Ok(self.start_node())
                     } else {
-                        false
-                    };
-
-                    let mut chars = HashSet::new();
-                    loop {
-                        match reader.peek() {
-                            Some(']') => break,
-                            None => return Err("Expected closing brace. Got empty.".into()),
-                            _ => {
-                                let group_char = reader.pop();
-                                if let Some('-') = reader.peek() {
-                                    reader.assert_pop('-')?;
-                                    let until_char = reader.pop();
-
-                                    chars.insert(Literal::Range {
-                                        start: *group_char,
-                                        end: *until_char,
-                                    });
-                                } else {
-                                    chars.insert(Literal::Char(*group_char));
-                                }
-                            }
-                        }
+                        self.check_modifier(reader, self.char_node('^'))
                     }
-
-                    reader.assert_pop(']')?;
-
-                    Ok(Self::check_modifier(
-                        reader,
-                        AstNode::CharGroup { is_negated, chars },
-                    )?)
                 }
                 '^' => {
-                    reader.pop();
-                    Ok(Self::check_modifier(reader, AstNode::Start)?)
+                    reader.pop()?;
+                    Ok(self.check_modifier(reader, self.start_node())?)
+                }
+                '$' if self.dialect == Dialect::Basic => {
+                    reader.pop()?;
+                    // BRE `$` is only an anchor at the end of an alternative.
+                    if reader.peek().is_none()
+                        || matches!(Self::peek_meta(self.dialect, reader), Some(')') | Some('|'))
+                    {
+                        Ok(self.end_node())
+                    } else {
+                        self.check_modifier(reader, self.char_node('$'))
+                    }
                 }
                 '$' => {
-                    reader.pop();
-                    Ok(Self::check_modifier(reader, AstNode::End)?)
+                    reader.pop()?;
+                    Ok(self.check_modifier(reader, self.end_node())?)
                 }
                 '.' => {
-                    reader.pop();
-                    Ok(Self::check_modifier(reader, AstNode::AnyChar)?)
+                    reader.pop()?;
+                    Ok(self.check_modifier(
+                        reader,
+                        AstNode::AnyChar {
+                            dot_all: self.flags.dot_all,
+                        },
+                    )?)
                 }
                 '\\' => {
-                    reader.pop();
+                    let escape_pos = reader.pos();
+                    reader.pop()?;
+
+                    if let Some(c) = Self::parse_char_escape(reader, escape_pos)? {
+                        return self.check_modifier(reader, self.char_node(c));
+                    }
+
                     match reader.peek() {
                         Some(peeked_c) => match peeked_c {
-                            'd' => {
-                                reader.pop();
-                                Ok(Self::check_modifier(
+                            c if SHORTHAND_CLASSES.contains(*c) => {
+                                let class = self.shorthand_class(*reader.pop()?);
+                                Ok(self.check_modifier(reader, AstNode::Char(class))?)
+                            }
+                            'p' | 'P' => {
+                                let is_negated = *reader.pop()? == 'P';
+                                let class = Self::parse_property(reader, escape_pos, is_negated)?;
+                                Ok(self.check_modifier(reader, AstNode::Char(class))?)
+                            }
+                            'b' | 'B' => {
+                                let is_negated = *reader.pop()? == 'B';
+                                let unicode = self.flags.unicode;
+                                Ok(self.check_modifier(
+                                    reader,
+                                    AstNode::WordBoundary {
+                                        is_negated,
+                                        unicode,
+                                    },
+                                )?)
+                            }
+                            '<' => {
+                                reader.pop()?;
+                                let unicode = self.flags.unicode;
+                                Ok(self.check_modifier(reader, AstNode::WordStart { unicode })?)
+                            }
+                            '>' => {
+                                reader.pop()?;
+                                let unicode = self.flags.unicode;
+                                Ok(self.check_modifier(reader, AstNode::WordEnd { unicode })?)
+                            }
+                            // `^` and `$` of the whole subject, regardless of `(?m)`.
+                            'A' => {
+                                reader.pop()?;
+                                Ok(self
+                                    .check_modifier(reader, AstNode::Start { multi_line: false })?)
+                            }
+                            'z' => {
+                                reader.pop()?;
+                                Ok(self
+                                    .check_modifier(reader, AstNode::End { multi_line: false })?)
+                            }
+                            '1'..='9' => {
+                                let id = Self::parse_number(reader)?;
+                                if id >= self.capture_id_provider.peek() {
+                                    return Err(PatternError::InvalidBackReference {
+                                        pos: escape_pos,
+                                        id,
+                                    });
+                                }
+                                Ok(self.check_modifier(
                                     reader,
-                                    AstNode::Char(crate::cond::Literal::Numeric),
+                                    AstNode::CaptureRef {
+                                        id,
+                                        ignore_case: self.flags.ignore_case,
+                                    },
                                 )?)
                             }
-                            'w' => {
-                                reader.pop();
-                                Ok(Self::check_modifier(
+                            'k' => {
+                                reader.pop()?;
+                                let name = Self::parse_group_name(reader)?;
+                                let Some(id) = self.capture_names.get(&name).copied() else {
+                                    return Err(PatternError::UndefinedGroupName {
+                                        pos: escape_pos,
+                                        name,
+                                    });
+                                };
+                                Ok(self.check_modifier(
                                     reader,
-                                    AstNode::Char(crate::cond::Literal::Alphanumeric),
+                                    AstNode::CaptureRef {
+                                        id,
+                                        ignore_case: self.flags.ignore_case,
+                                    },
                                 )?)
                             }
-                            '1'..'9' => {
-                                let id_raw = reader.parse_while(|c| c.is_ascii_digit());
-                                let id =
-                                    u64::from_str_radix(&id_raw.iter().collect::<String>(), 10)
-                                        .unwrap();
-                                Ok(Self::check_modifier(reader, AstNode::CaptureRef(id))?)
+                            'Q' => {
+                                reader.pop()?;
+                                self.parse_quoted(reader)
                             }
                             other => {
-                                reader.pop();
-                                Ok(Self::check_modifier(
-                                    reader,
-                                    AstNode::Char(Literal::Char(*other)),
-                                )?)
+                                reader.pop()?;
+                                Ok(self.check_modifier(reader, self.char_node(*other))?)
                             }
                         },
-                        None => panic!("Missing char after \\"),
+                        None => Err(PatternError::TrailingBackslash { pos: escape_pos }),
                     }
                 }
                 other => {
-                    reader.pop(); // char
-                    Ok(Self::check_modifier(
-                        reader,
-                        AstNode::Char(crate::cond::Literal::Char(*other)),
-                    )?)
+                    reader.pop()?; // char
+                    Ok(self.check_modifier(reader, self.char_node(*other))?)
                 }
             },
-            None => Err("No more input to read".into()),
+            None => Err(PatternError::UnexpectedEnd { pos: reader.pos() }),
         }
     }
 
-    fn check_modifier(reader: &mut Reader<'_, char>, node: AstNode) -> Result<AstNode, Error> {
-        match reader.peek() {
+    /// Parses a bracket expression. A `]` right after the opening (and the negation) is a literal,
+    /// just as a `-` that cannot make a range.
+    fn parse_bracket(&self, reader: &mut Reader<'_, char>) -> Result<AstNode, PatternError> {
+        let open_pos = reader.pos();
+        reader.assert_pop('[')?;
+        let is_negated = if let Some('^') = reader.peek() {
+            reader.assert_pop('^')?;
+            true
+        } else {
+            false
+        };
+
+        let mut chars = HashSet::new();
+        let mut is_first = true;
+        loop {
+            match reader.peek() {
+                Some(']') if !is_first => break,
+                None => return Err(PatternError::UnmatchedBracket { pos: open_pos }),
+                _ => {}
+            }
+            is_first = false;
+
+            let item_pos = reader.pos();
+            let item = self.parse_bracket_item(reader, open_pos)?;
+
+            let is_range = matches!(item, Literal::Char(_))
+                && reader.peek() == Some(&'-')
+                && !matches!(reader.peek_nth(1), Some(']') | None);
+            if !is_range {
+                chars.insert(item);
+                continue;
+            }
+
+            reader.assert_pop('-')?;
+            match (item, self.parse_bracket_item(reader, open_pos)?) {
+                (Literal::Char(start), Literal::Char(end)) if start <= end => {
+                    chars.insert(Literal::Range { start, end });
+                }
+                _ => return Err(PatternError::InvalidRange { pos: item_pos }),
+            }
+        }
+
+        reader.assert_pop(']')?;
+
+        Ok(AstNode::CharGroup {
+            is_negated,
+            chars,
+            ignore_case: self.flags.ignore_case,
+        })
+    }
+
+    /// A single char, a shorthand escape or a `[:class:]` (also the single char `[.c.]` and `[=c=]`
+    /// forms) of a bracket expression.
+    fn parse_bracket_item(
+        &self,
+        reader: &mut Reader<'_, char>,
+        open_pos: usize,
+    ) -> Result<Literal, PatternError> {
+        let unmatched = |_| PatternError::UnmatchedBracket { pos: open_pos };
+        let item_pos = reader.pos();
+
+        match reader.pop().map_err(unmatched)? {
+            '\\' => {
+                if let Some(c) = Self::parse_char_escape(reader, item_pos)? {
+                    return Ok(Literal::Char(c));
+                }
+
+                let c = *reader.pop().map_err(unmatched)?;
+                if SHORTHAND_CLASSES.contains(c) {
+                    Ok(self.shorthand_class(c))
+                } else if c == 'p' || c == 'P' {
+                    Self::parse_property(reader, item_pos, c == 'P')
+                } else {
+                    Ok(Literal::Char(c))
+                }
+            }
+            '[' if matches!(reader.peek(), Some(':') | Some('.') | Some('=')) => {
+                let delimiter = *reader.pop()?;
+                let name = reader
+                    .parse_while(|c| *c != delimiter && *c != ']')
+                    .iter()
+                    .collect::<String>();
+                reader.assert_pop(delimiter).map_err(unmatched)?;
+                reader.assert_pop(']').map_err(unmatched)?;
+
+                if delimiter == ':' {
+                    return PosixClass::from_name(&name).map(Literal::Posix).ok_or(
+                        PatternError::UnknownCharClass {
+                            pos: item_pos,
+                            name,
+                        },
+                    );
+                }
+
+                // Only single char collating elements and equivalence classes are supported.
+                let mut name_chars = name.chars();
+                match (name_chars.next(), name_chars.next()) {
+                    (Some(c), None) => Ok(Literal::Char(c)),
+                    _ => Err(PatternError::UnknownCharClass {
+                        pos: item_pos,
+                        name,
+                    }),
+                }
+            }
+            c => Ok(Literal::Char(*c)),
+        }
+    }
+
+    /// Parses the `?<name>` or `?P<name>` part of a named group and registers the name. Named groups
+    /// are numbered along with the unnamed ones.
+    fn parse_group_name_prefix(
+        &mut self,
+        reader: &mut Reader<'_, char>,
+    ) -> Result<u64, PatternError> {
+        reader.assert_pop('?')?;
+        if let Some('P') = reader.peek() {
+            reader.assert_pop('P')?;
+        }
+
+        let name_pos = reader.pos() + 1;
+        let name = Self::parse_group_name(reader)?;
+        if self.capture_names.contains_key(&name) {
+            return Err(PatternError::DuplicateGroupName {
+                pos: name_pos,
+                name,
+            });
+        }
+
+        let capture_id = self.capture_id_provider.get();
+        self.capture_names.insert(name, capture_id);
+        Ok(capture_id)
+    }
+
+    /// Parses `<name>`, where the name is made of word chars and does not start with a digit.
+    fn parse_group_name(reader: &mut Reader<'_, char>) -> Result<String, PatternError> {
+        reader.assert_pop('<')?;
+        let name_pos = reader.pos();
+        let name = reader.parse_while(|c| is_word_char(*c));
+
+        if name.is_empty() || name[0].is_ascii_digit() {
+            return Err(PatternError::InvalidGroupName { pos: name_pos });
+        }
+        reader.assert_pop('>')?;
+
+        Ok(name.iter().collect())
+    }
+
+    /// Parses the `?flags)` or `?flags:` part of a flag group and applies the flags. Returns whether
+    /// a scoped group (`:`) follows.
+    fn parse_flags(&mut self, reader: &mut Reader<'_, char>) -> Result<bool, PatternError> {
+        reader.assert_pop('?')?;
+        let mut is_enabling = true;
+
+        loop {
+            let pos = reader.pos();
+            match reader.pop()? {
+                ')' => return Ok(false),
+                ':' => return Ok(true),
+                '-' if is_enabling => is_enabling = false,
+                'i' => self.flags.ignore_case = is_enabling,
+                'm' => self.flags.multi_line = is_enabling,
+                's' => self.flags.dot_all = is_enabling,
+                'x' => self.flags.extended = is_enabling,
+                'U' => self.flags.swap_greed = is_enabling,
+                other => {
+                    return Err(PatternError::UnknownFlag { pos, flag: *other });
+                }
+            }
+        }
+    }
+
+    /// In extended mode skips the whitespace and the `#` comments (until the end of the line).
+    fn skip_extended_whitespace(&self, reader: &mut Reader<'_, char>) {
+        if !self.flags.extended {
+            return;
+        }
+
+        loop {
+            reader.parse_while(|c| c.is_whitespace());
+            if reader.peek() != Some(&'#') {
+                break;
+            }
+            reader.parse_while(|c| *c != '\n');
+        }
+    }
+
+    fn start_node(&self) -> AstNode {
+        AstNode::Start {
+            multi_line: self.flags.multi_line,
+        }
+    }
+
+    fn end_node(&self) -> AstNode {
+        AstNode::End {
+            multi_line: self.flags.multi_line,
+        }
+    }
+
+    /// Parses an escape that stands for a single char: `\t`, `\n`, `\r`, `\f`, `\e`, `\a`, `\xHH`,
+    /// `\x{H...}`, `\uHHHH` and `\0` (with up to two more octal digits). The reader is right after
+    /// the backslash, nothing is consumed if the escape is something else.
+    fn parse_char_escape(
+        reader: &mut Reader<'_, char>,
+        escape_pos: usize,
+    ) -> Result<Option<char>, PatternError> {
+        let invalid = || PatternError::InvalidEscape { pos: escape_pos };
+
+        let code_point = match reader.peek() {
+            Some('t') => Some('\t' as u32),
+            Some('n') => Some('\n' as u32),
+            Some('r') => Some('\r' as u32),
+            Some('f') => Some('\x0C' as u32),
+            Some('e') => Some('\x1B' as u32),
+            Some('a') => Some('\x07' as u32),
+            _ => None,
+        };
+        if code_point.is_some() {
+            reader.pop()?;
+        }
+
+        let code_point = match (code_point, reader.peek()) {
+            (Some(code_point), _) => code_point,
+            (None, Some('x')) if reader.peek_nth(1) == Some(&'{') => {
+                reader.assert_pop('x')?;
+                reader.assert_pop('{')?;
+                let code_point = Self::parse_code_point(reader, 16, 1, 6).ok_or_else(invalid)?;
+                reader.assert_pop('}').map_err(|_| invalid())?;
+                code_point
+            }
+            (None, Some('x')) => {
+                reader.assert_pop('x')?;
+                Self::parse_code_point(reader, 16, 1, 2).ok_or_else(invalid)?
+            }
+            (None, Some('u')) => {
+                reader.assert_pop('u')?;
+                Self::parse_code_point(reader, 16, 4, 4).ok_or_else(invalid)?
+            }
+            (None, Some('0')) => {
+                reader.assert_pop('0')?;
+                Self::parse_code_point(reader, 8, 0, 2).unwrap_or(0)
+            }
+            _ => return Ok(None),
+        };
+
+        char::from_u32(code_point).map(Some).ok_or_else(invalid)
+    }
+
+    /// Reads `min_len..=max_len` digits of `radix`, `None` if there are fewer.
+    fn parse_code_point(
+        reader: &mut Reader<'_, char>,
+        radix: u32,
+        min_len: usize,
+        max_len: usize,
+    ) -> Option<u32> {
+        let mut code_point = 0u32;
+        let mut len = 0;
+
+        while len < max_len
+            && let Some(digit) = reader.peek().and_then(|c| c.to_digit(radix))
+        {
+            reader.pop().ok()?;
+            code_point = code_point * radix + digit;
+            len += 1;
+        }
+
+        (len >= min_len).then_some(code_point)
+    }
+
+    /// The chars of a `\Q...\E` span are all literals, the span lasts until the end of the pattern
+    /// if `\E` is missing. A quantifier after it applies to the last char.
+    fn parse_quoted(&self, reader: &mut Reader<'_, char>) -> Result<AstNode, PatternError> {
+        let mut nodes = vec![];
+
+        while let Some(c) = reader.peek() {
+            if *c == '\\' && reader.peek_nth(1) == Some(&'E') {
+                reader.assert_pop('\\')?;
+                reader.assert_pop('E')?;
+                break;
+            }
+
+            reader.pop()?;
+            nodes.push(self.char_node(*c));
+        }
+
+        if let Some(last) = nodes.pop() {
+            nodes.push(self.check_modifier(reader, last)?);
+        }
+
+        Ok(AstNode::Seq(nodes))
+    }
+
+    /// Parses the name of a `\p{Name}` (or `\pL`) property, the reader is right after the `p`.
+    fn parse_property(
+        reader: &mut Reader<'_, char>,
+        escape_pos: usize,
+        is_negated: bool,
+    ) -> Result<Literal, PatternError> {
+        let name = if let Some('{') = reader.peek() {
+            reader.assert_pop('{')?;
+            let name = reader.parse_while(|c| *c != '}').iter().collect::<String>();
+            reader.assert_pop('}')?;
+            name
+        } else {
+            reader.pop()?.to_string()
+        };
+
+        match property_ranges(&name) {
+            Some((name, ranges)) => Ok(Literal::Property {
+                name,
+                ranges,
+                is_negated,
+            }),
+            None => Err(PatternError::UnknownProperty {
+                pos: escape_pos,
+                name,
+            }),
+        }
+    }
+
+    /// The class of a shorthand escape, `c` is one of `SHORTHAND_CLASSES`.
+    fn shorthand_class(&self, c: char) -> Literal {
+        match (c, self.flags.unicode) {
+            ('d' | 'D', true) => Literal::Property {
+                name: "Nd",
+                ranges: decimal_number_ranges(),
+                is_negated: c == 'D',
+            },
+            ('w', true) => Literal::UnicodeAlphanumeric,
+            ('W', true) => Literal::NonUnicodeAlphanumeric,
+            ('s', true) => Literal::UnicodeWhitespace,
+            ('S', true) => Literal::NonUnicodeWhitespace,
+            _ => Self::ascii_shorthand_class(c),
+        }
+    }
+
+    fn ascii_shorthand_class(c: char) -> Literal {
+        match c {
+            'd' => Literal::Numeric,
+            'D' => Literal::NonNumeric,
+            'w' => Literal::Alphanumeric,
+            'W' => Literal::NonAlphanumeric,
+            's' => Literal::Whitespace,
+            'S' => Literal::NonWhitespace,
+            'h' => Literal::HorizontalWhitespace,
+            'v' => Literal::VerticalWhitespace,
+            other => unreachable!("not a shorthand class: \\{}", other),
+        }
+    }
+
+    /// A single literal char. Under case-insensitive matching it becomes a one element group, which
+    /// is the node that does the case folding.
+    fn char_node(&self, c: char) -> AstNode {
+        if self.flags.ignore_case {
+            AstNode::CharGroup {
+                is_negated: false,
+                chars: HashSet::from([Literal::Char(c)]),
+                ignore_case: true,
+            }
+        } else {
+            AstNode::Char(Literal::Char(c))
+        }
+    }
+
+    fn check_modifier(
+        &self,
+        reader: &mut Reader<'_, char>,
+        node: AstNode,
+    ) -> Result<AstNode, PatternError> {
+        self.skip_extended_whitespace(reader);
+        let modifier = match reader.peek() {
+            Some('*') => Some('*'),
+            _ => Self::peek_meta(self.dialect, reader).filter(|c| matches!(c, '?' | '+' | '{')),
+        };
+
+        let (min, max) = match modifier {
             Some('*') => {
-                reader.pop();
-                Ok(AstNode::Repeat {
-                    min: None,
-                    max: None,
-                    node: Box::new(node),
-                })
+                reader.pop()?;
+                (None, None)
             }
             Some('?') => {
-                reader.pop();
-                Ok(AstNode::Repeat {
-                    min: None,
-                    max: Some(1),
-                    node: Box::new(node),
-                })
+                self.assert_pop_meta(reader, '?')?;
+                (None, Some(1))
             }
             Some('+') => {
-                reader.pop();
-                Ok(AstNode::Repeat {
-                    min: Some(1),
-                    max: None,
-                    node: Box::new(node),
-                })
+                self.assert_pop_meta(reader, '+')?;
+                (Some(1), None)
             }
             Some('{') => {
-                reader.pop();
+                let open_pos = reader.pos();
+                self.assert_pop_meta(reader, '{')?;
                 let min = Some(Self::parse_number(reader)?);
                 let max = if let Some(',') = reader.peek() {
-                    reader.pop(); // comma
+                    reader.pop()?; // comma
 
-                    if let Some('}') = reader.peek() {
+                    if let Some('}') = Self::peek_meta(self.dialect, reader) {
                         None
                     } else {
                         Some(Self::parse_number(reader)?)
@@ -226,28 +819,63 @@ impl Parser {
                 } else {
                     min
                 };
-                reader.assert_pop('}')?;
+                self.assert_pop_meta(reader, '}')?;
 
-                Ok(AstNode::Repeat {
-                    min,
-                    max,
-                    node: Box::new(node),
-                })
+                if let (Some(min), Some(max)) = (min, max)
+                    && max < min
+                {
+                    return Err(PatternError::InvalidRepeatRange {
+                        pos: open_pos,
+                        min,
+                        max,
+                    });
+                }
+
+                (min, max)
             }
-            _ => Ok(node),
+            _ => return Ok(node),
+        };
+
+        // A `?` right after the quantifier makes it lazy, a `+` possessive (ERE only, these are
+        // literals in BRE).
+        // `(?U)` swaps the meaning of the `?`.
+        let has_lazy_suffix = self.dialect == Dialect::Extended && reader.peek() == Some(&'?');
+        let is_possessive = self.dialect == Dialect::Extended && reader.peek() == Some(&'+');
+        if has_lazy_suffix || is_possessive {
+            reader.pop()?;
+        }
+        let is_lazy = !is_possessive && has_lazy_suffix != self.flags.swap_greed;
+
+        let repeat = AstNode::Repeat {
+            min,
+            max,
+            is_lazy,
+            node: Box::new(node),
+        };
+
+        if is_possessive {
+            Ok(AstNode::Atomic(Box::new(repeat)))
+        } else {
+            Ok(repeat)
         }
     }
 
-    fn parse_number(reader: &mut Reader<'_, char>) -> Result<u64, Error> {
+    fn parse_number(reader: &mut Reader<'_, char>) -> Result<u64, PatternError> {
+        let pos = reader.pos();
         let raw = reader.parse_while(|c| c.is_ascii_digit());
         let raw_str: String = raw.iter().collect();
-        u64::from_str_radix(&raw_str, 10).map_err(|err| err.into())
+        raw_str
+            .parse::<u64>()
+            .map_err(|_| PatternError::InvalidNumber { pos })
     }
 }
 
 #[cfg(test)]
 mod test {
-    use crate::parser::Parser;
+    use crate::{
+        error::PatternError,
+        parser::{Dialect, Flags, Parser},
+    };
 
     #[test]
     fn test_parsing() {
@@ -255,4 +883,128 @@ mod test {
         dbg!(Parser::parse_regex_str("x(a|bc|([0-3]|.*))").unwrap());
         dbg!(Parser::parse_regex_str("\\d+").unwrap());
     }
+
+    #[test]
+    fn test_parse_errors() {
+        assert_eq!(
+            Parser::parse_regex_str("ab(cd").unwrap_err(),
+            PatternError::UnmatchedOpenParen { pos: 2 }
+        );
+        assert_eq!(
+            Parser::parse_regex_str("ab)").unwrap_err(),
+            PatternError::UnmatchedCloseParen { pos: 2 }
+        );
+        assert_eq!(
+            Parser::parse_regex_str("[a-").unwrap_err(),
+            PatternError::UnmatchedBracket { pos: 0 }
+        );
+        assert_eq!(
+            Parser::parse_regex_str("a\\").unwrap_err(),
+            PatternError::TrailingBackslash { pos: 1 }
+        );
+        assert_eq!(
+            Parser::parse_regex_str("x{3,1}").unwrap_err(),
+            PatternError::InvalidRepeatRange {
+                pos: 1,
+                min: 3,
+                max: 1
+            }
+        );
+        assert_eq!(
+            Parser::parse_regex_str("x{a}").unwrap_err(),
+            PatternError::InvalidNumber { pos: 2 }
+        );
+        assert_eq!(
+            Parser::parse_regex_str("(a)\\2").unwrap_err(),
+            PatternError::InvalidBackReference { pos: 3, id: 2 }
+        );
+        assert_eq!(
+            Parser::parse_regex_str("(?:a)(b)\\2").unwrap_err(),
+            PatternError::InvalidBackReference { pos: 8, id: 2 }
+        );
+        assert_eq!(
+            Parser::parse_regex_str("(?<1a>x)").unwrap_err(),
+            PatternError::InvalidGroupName { pos: 3 }
+        );
+        assert_eq!(
+            Parser::parse_regex_str("(?<a>x)(?P<a>y)").unwrap_err(),
+            PatternError::DuplicateGroupName {
+                pos: 11,
+                name: "a".to_string()
+            }
+        );
+        assert_eq!(
+            Parser::parse_regex_str("(?<a>x)\\k<b>").unwrap_err(),
+            PatternError::UndefinedGroupName {
+                pos: 7,
+                name: "b".to_string()
+            }
+        );
+        assert_eq!(
+            Parser::parse_regex_str("a(?<=b").unwrap_err(),
+            PatternError::UnmatchedOpenParen { pos: 1 }
+        );
+        assert_eq!(
+            Parser::parse_regex_str("a[z-a]").unwrap_err(),
+            PatternError::InvalidRange { pos: 2 }
+        );
+        assert_eq!(
+            Parser::parse_regex_str("[a-\\d]").unwrap_err(),
+            PatternError::InvalidRange { pos: 1 }
+        );
+        assert_eq!(
+            Parser::parse_regex_str("[[:alfa:]]").unwrap_err(),
+            PatternError::UnknownCharClass {
+                pos: 1,
+                name: "alfa".to_string()
+            }
+        );
+        assert_eq!(
+            Parser::parse_regex_str("[]").unwrap_err(),
+            PatternError::UnmatchedBracket { pos: 0 }
+        );
+        assert_eq!(
+            Parser::parse_regex_str("[[:alpha:]").unwrap_err(),
+            PatternError::UnmatchedBracket { pos: 0 }
+        );
+        assert_eq!(
+            Parser::parse_regex_str("a\\p{Klingon}").unwrap_err(),
+            PatternError::UnknownProperty {
+                pos: 1,
+                name: "Klingon".to_string()
+            }
+        );
+        assert_eq!(
+            Parser::parse_regex_str("a\\xg").unwrap_err(),
+            PatternError::InvalidEscape { pos: 1 }
+        );
+        assert_eq!(
+            Parser::parse_regex_str("\\x{110000}").unwrap_err(),
+            PatternError::InvalidEscape { pos: 0 }
+        );
+        assert_eq!(
+            Parser::parse_regex_str("\\u12").unwrap_err(),
+            PatternError::InvalidEscape { pos: 0 }
+        );
+        assert_eq!(
+            Parser::parse_regex_str("(?iq:a)").unwrap_err(),
+            PatternError::UnknownFlag { pos: 3, flag: 'q' }
+        );
+        assert_eq!(
+            Parser::parse_regex_str("a(?#b").unwrap_err(),
+            PatternError::UnmatchedOpenParen { pos: 1 }
+        );
+        assert_eq!(
+            Parser::parse_regex_str("(?:ab").unwrap_err(),
+            PatternError::UnmatchedOpenParen { pos: 0 }
+        );
+        assert_eq!(
+            Parser::parse_regex_str_with("ab\\(cd", Dialect::Basic, Flags::default()).unwrap_err(),
+            PatternError::UnmatchedOpenParen { pos: 2 }
+        );
+        assert_eq!(
+            Parser::parse_regex_str_with("ab\\)", Dialect::Basic, Flags::default()).unwrap_err(),
+            PatternError::UnmatchedCloseParen { pos: 2 }
+        );
+    }
 }
@@ -0,0 +1,555 @@
+use crate::{
+    ast::AstNode,
+    capturer::Capturer,
+    common::{is_word_token, range_end_adjust, range_start_adjust, str_to_tokens},
+    error::PatternError,
+    evaluator::{EvalMatchResult, Evaluator},
+    literal::LiteralMatcher,
+    parser::{Dialect, Flags, Parser},
+    token::Token,
+};
+
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct ProgramOptions {
+    pub(crate) dialect: Dialect,
+    pub(crate) flags: Flags,
+    /// Matches must cover the whole line (`-x`).
+    pub(crate) line_regexp: bool,
+    /// Matches must not be surrounded by word chars (`-w`).
+    pub(crate) word_regexp: bool,
+    /// Patterns are literal strings, not regular expressions (`-F`).
+    pub(crate) fixed_strings: bool,
+}
+
+/// A pattern of the list that failed to compile.
+#[derive(Debug)]
+pub(crate) struct InvalidPattern<'a> {
+    pub(crate) pattern: &'a str,
+    pub(crate) error: PatternError,
+}
+
+enum Matcher {
+    Regex(Vec<Evaluator>),
+    Literal(LiteralMatcher),
+}
+
+/// Patterns compiled once into their transition graphs (or literal searchers), ready to be run
+/// against any number of lines. A line matches if any of the patterns matches.
+pub(crate) struct Program {
+    matcher: Matcher,
+    line_regexp: bool,
+    word_regexp: bool,
+    // Word chars of `-w` are the Unicode ones.
+    unicode: bool,
+}
+
+impl Program {
+    pub(crate) fn compile(
+        patterns: &[String],
+        options: ProgramOptions,
+    ) -> Result<Self, InvalidPattern<'_>> {
+        // A whole line match has nothing around it to check.
+        let word_regexp = options.word_regexp && !options.line_regexp;
+
+        if options.fixed_strings {
+            return Ok(Self {
+                matcher: Matcher::Literal(LiteralMatcher::new(patterns, options.flags.ignore_case)),
+                line_regexp: options.line_regexp,
+                word_regexp,
+                unicode: options.flags.unicode,
+            });
+        }
+
+        let mut evaluators = vec![];
+
+        for pattern in patterns {
+            let mut ast_root =
+                Parser::parse_regex_str_with(pattern, options.dialect, options.flags)
+                    .map_err(|error| InvalidPattern { pattern, error })?;
+
+            if options.line_regexp {
+                ast_root = Self::anchor_to_line(ast_root);
+            }
+
+            evaluators.push(Evaluator::new(ast_root.generate()));
+        }
+
+        Ok(Self {
+            matcher: Matcher::Regex(evaluators),
+            line_regexp: options.line_regexp,
+            word_regexp,
+            unicode: options.flags.unicode,
+        })
+    }
+
+    /// Wraps the pattern as `^(?:pattern)$`.
+    fn anchor_to_line(ast_root: AstNode) -> AstNode {
+        let inner = match ast_root {
+            AstNode::Root(inner) => *inner,
+            other => other,
+        };
+
+        AstNode::Root(Box::new(AstNode::Seq(vec![
+            AstNode::Start { multi_line: false },
+            AstNode::Alt {
+                options: vec![inner],
+                id: None,
+            },
+            AstNode::End { multi_line: false },
+        ])))
+    }
+
+    /// Token ranges of the leftmost, non-overlapping matches of any of the patterns. Of the
+    /// patterns matching at the same start the longest match wins.
+    fn regex_matches(&self, evaluators: &[Evaluator], tokens: &[Token]) -> Vec<(usize, usize)> {
+        let mut pattern_matches = vec![];
+        let mut offset = 0;
+
+        while offset < tokens.len() {
+            let accept =
+                |len| !self.word_regexp || self.is_word_bounded(tokens, (offset, offset + len));
+            let longest = evaluators
+                .iter()
+                .filter_map(|evaluator| {
+                    evaluator.first_match(tokens, offset, Capturer::new(), accept)
+                })
+                .map(|(len, _)| len)
+                .max();
+
+            match longest {
+                Some(len) => {
+                    pattern_matches.push((offset, offset + len));
+                    // `max(offset + 1)` ensures the scanner is not stuck with valid empty matches.
+                    offset = (offset + len).max(offset + 1);
+                }
+                None => offset += 1,
+            }
+        }
+
+        pattern_matches
+    }
+
+    /// Token ranges of the leftmost-longest, non-overlapping literal occurrences.
+    fn literal_matches(
+        &self,
+        literal_matcher: &LiteralMatcher,
+        line: &str,
+        tokens: &[Token],
+    ) -> Vec<(usize, usize)> {
+        let chars = line.chars().collect::<Vec<_>>();
+        let mut occurrences = literal_matcher.occurrences(&chars);
+        // Longest first among the ones with the same start.
+        occurrences.sort_by_key(|(start, end)| (*start, usize::MAX - end));
+
+        let mut pattern_matches = vec![];
+        let mut next_start = 0;
+
+        for (start, end) in occurrences {
+            // Compensate for the <start> token.
+            let range = (start + 1, end + 1);
+
+            if start < next_start
+                || (self.line_regexp && (start != 0 || end != chars.len()))
+                || (self.word_regexp && !self.is_word_bounded(tokens, range))
+            {
+                continue;
+            }
+
+            pattern_matches.push(range);
+            // `max(start + 1)` ensures empty matches are not repeated.
+            next_start = end.max(start + 1);
+        }
+
+        pattern_matches
+    }
+
+    /// Whether the token range has no word char right before or after it.
+    fn is_word_bounded(&self, tokens: &[Token], (start, end): (usize, usize)) -> bool {
+        let before = if start == 0 {
+            None
+        } else {
+            tokens.get(start - 1)
+        };
+
+        !is_word_token(before, self.unicode) && !is_word_token(tokens.get(end), self.unicode)
+    }
+
+    /// Matches the line. Match ranges are byte ranges into `line`.
+    pub(crate) fn is_match(&self, line: &str) -> EvalMatchResult {
+        let tokens = str_to_tokens(line);
+        let pattern_matches = match &self.matcher {
+            Matcher::Regex(evaluators) => self.regex_matches(evaluators, &tokens),
+            Matcher::Literal(literal_matcher) => {
+                self.literal_matches(literal_matcher, line, &tokens)
+            }
+        };
+
+        if pattern_matches.is_empty() {
+            return EvalMatchResult::NoMatch;
+        }
+
+        // Byte offset of each char, plus the end of the line.
+        let byte_offsets = line
+            .char_indices()
+            .map(|(i, _)| i)
+            .chain([line.len()])
+            .collect::<Vec<_>>();
+        let char_count = byte_offsets.len() - 1;
+
+        EvalMatchResult::Match {
+            matches: pattern_matches
+                .into_iter()
+                .map(|(start, end)| {
+                    (
+                        byte_offsets[range_start_adjust(start)],
+                        byte_offsets[range_end_adjust(end, char_count)],
+                    )
+                })
+                .collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        evaluator::EvalMatchResult,
+        parser::{Dialect, Flags},
+        program::{Program, ProgramOptions},
+    };
+
+    fn compile(patterns: &[&str]) -> Program {
+        compile_with_options(patterns, ProgramOptions::default())
+    }
+
+    fn compile_with_options(patterns: &[&str], options: ProgramOptions) -> Program {
+        let patterns = patterns.iter().map(|p| p.to_string()).collect::<Vec<_>>();
+        Program::compile(&patterns, options).unwrap()
+    }
+
+    #[test]
+    fn test_program_reuse() {
+        let program = compile(&["(ab|cd)+x"]);
+
+        assert!(program.is_match("ababx").is_match());
+        assert!(!program.is_match("abab").is_match());
+        assert!(program.is_match("__cdabx").is_match());
+    }
+
+    #[test]
+    fn test_byte_ranges() {
+        let program = compile(&["b+"]);
+
+        match program.is_match("ébb") {
+            EvalMatchResult::Match { matches } => assert_eq!(vec![(2, 4)], matches),
+            EvalMatchResult::NoMatch => panic!("expected a match"),
+        }
+    }
+
+    #[test]
+    fn test_multiple_patterns() {
+        let program = compile(&["cat", "dog", "at d"]);
+
+        assert!(program.is_match("hotdog").is_match());
+        assert!(!program.is_match("cow").is_match());
+
+        match program.is_match("a cat dog cat") {
+            EvalMatchResult::Match { matches } => {
+                assert_eq!(vec![(2, 5), (6, 9), (10, 13)], matches)
+            }
+            EvalMatchResult::NoMatch => panic!("expected a match"),
+        }
+
+        // Matches of different patterns are not merged.
+        let program = compile(&["ab", "cd"]);
+        match program.is_match("abcd") {
+            EvalMatchResult::Match { matches } => assert_eq!(vec![(0, 2), (2, 4)], matches),
+            EvalMatchResult::NoMatch => panic!("expected a match"),
+        }
+        let program = compile(&["1", "x"]);
+        match program.is_match("11") {
+            EvalMatchResult::Match { matches } => assert_eq!(vec![(0, 1), (1, 2)], matches),
+            EvalMatchResult::NoMatch => panic!("expected a match"),
+        }
+    }
+
+    #[test]
+    fn test_line_regexp() {
+        let options = ProgramOptions {
+            line_regexp: true,
+            ..ProgramOptions::default()
+        };
+        let program = compile_with_options(&["cat|dog"], options);
+
+        assert!(program.is_match("dog").is_match());
+        assert!(!program.is_match("hotdog").is_match());
+        assert!(!program.is_match("cats").is_match());
+    }
+
+    #[test]
+    fn test_word_regexp() {
+        let options = ProgramOptions {
+            word_regexp: true,
+            ..ProgramOptions::default()
+        };
+
+        let program = compile_with_options(&["cat"], options);
+        assert!(program.is_match("a cat.").is_match());
+        assert!(!program.is_match("cats").is_match());
+
+        // The leftmost match fails, a later one passes.
+        match program.is_match("cats cat") {
+            EvalMatchResult::Match { matches } => assert_eq!(vec![(5, 8)], matches),
+            EvalMatchResult::NoMatch => panic!("expected a match"),
+        }
+
+        // The longest match fails, a shorter one from the same start passes.
+        let program = compile_with_options(&["ab*"], options);
+        match program.is_match("abb-a abbc") {
+            EvalMatchResult::Match { matches } => assert_eq!(vec![(0, 3), (4, 5)], matches),
+            EvalMatchResult::NoMatch => panic!("expected a match"),
+        }
+    }
+
+    fn assert_matches(pattern: &str, line: &str, expected: &[(usize, usize)]) {
+        match compile(&[pattern]).is_match(line) {
+            EvalMatchResult::Match { matches } => assert_eq!(expected, matches, "{}", pattern),
+            EvalMatchResult::NoMatch => panic!("expected a match for {}", pattern),
+        }
+    }
+
+    #[test]
+    fn test_lazy_quantifiers() {
+        assert_matches("\".*?\"", "\"a\" and \"b\"", &[(0, 3), (8, 11)]);
+        assert_matches("\".*\"", "\"a\" and \"b\"", &[(0, 11)]);
+        assert_matches("a+?", "aaa", &[(0, 1), (1, 2), (2, 3)]);
+        assert_matches("ba??", "baa", &[(0, 1)]);
+        assert_matches("a{2,3}?", "aaaaa", &[(0, 2), (2, 4)]);
+        assert_matches("<(.+?)>x", "<a>b>x", &[(0, 6)]);
+    }
+
+    #[test]
+    fn test_possessive_and_atomic() {
+        assert_matches("a*+b", "aaab", &[(0, 4)]);
+        assert!(!compile(&["a*+a"]).is_match("aaa").is_match());
+        assert!(!compile(&["\".*+\""]).is_match("\"a\"").is_match());
+        assert_matches("x?+x", "xxx", &[(0, 2)]);
+        assert_matches("[0-9]{2}+", "1234", &[(0, 2), (2, 4)]);
+
+        assert!(!compile(&["(?>a|ab)c"]).is_match("abc").is_match());
+        assert!(compile(&["(?>ab|a)c"]).is_match("abc").is_match());
+        assert_matches("(?>a+)b", "aab", &[(0, 3)]);
+        // Captures made inside the atomic group stay visible after it.
+        assert!(compile(&["(?>(a|b))\\1"]).is_match("bb").is_match());
+        assert!(!compile(&["(?>(a|b))\\1"]).is_match("ba").is_match());
+        assert!(compile(&["(x)(?>\\1y)"]).is_match("xxy").is_match());
+    }
+
+    #[test]
+    fn test_shorthand_classes() {
+        assert_matches("\\s+", "a \t b", &[(1, 4)]);
+        assert_matches("\\S+", " ab c", &[(1, 3), (4, 5)]);
+        assert_matches("\\D+", "12ab3", &[(2, 4)]);
+        assert_matches("\\W", "a-b_c", &[(1, 2)]);
+        assert_matches("\\h", "a\tb\x0Bc", &[(1, 2)]);
+        assert_matches("\\v", "a\tb\x0Bc", &[(3, 4)]);
+        assert_matches("[\\d\\s]+", "ab1 2cd", &[(2, 5)]);
+        assert_matches("[^\\w]", "ab-", &[(2, 3)]);
+        assert!(!compile(&["\\s"]).is_match("s").is_match());
+    }
+
+    #[test]
+    fn test_bracket_expressions() {
+        assert_matches("[[:alpha:]]+", "ab12cd", &[(0, 2), (4, 6)]);
+        assert_matches("[[:digit:][:space:]]+", "a1 2b", &[(1, 4)]);
+        assert_matches("[[:punct:]]", "a,b", &[(1, 2)]);
+        assert_matches("[^[:alnum:]]", "ab-", &[(2, 3)]);
+        assert_matches("[[:upper:][:xdigit:]]+", "xAfgZ", &[(1, 3), (4, 5)]);
+        assert_matches("[\\d_]+", "a1_2b", &[(1, 4)]);
+        assert_matches("[]a]+", "b]a]", &[(1, 4)]);
+        assert_matches("[^]a]", "]ab", &[(2, 3)]);
+        assert_matches("[a-]+", "b-a-", &[(1, 4)]);
+        assert_matches("[-a]", "b-", &[(1, 2)]);
+        assert_matches("[\\]\\-]+", "a]-", &[(1, 3)]);
+        assert_matches("[[.-.]]", "a-", &[(1, 2)]);
+        assert_matches("[[=e=]]", "ae", &[(1, 2)]);
+    }
+
+    #[test]
+    fn test_unicode() {
+        assert_matches("\\p{Greek}+", "abc αβγ", &[(4, 10)]);
+        assert_matches("\\p{Lu}", "жЖ", &[(2, 4)]);
+        assert_matches("\\P{L}+", "ab12cd", &[(2, 4)]);
+        assert_matches("[\\p{Cyrillic}\\d]+", "ab ж1я", &[(3, 8)]);
+        assert_matches("\\pN", "x٣", &[(1, 3)]);
+        assert!(!compile(&["\\d"]).is_match("٣").is_match());
+        assert!(!compile(&["\\w"]).is_match("ж").is_match());
+
+        let options = ProgramOptions {
+            flags: Flags {
+                unicode: true,
+                ..Flags::default()
+            },
+            ..ProgramOptions::default()
+        };
+        let program = compile_with_options(&["\\w+\\s\\d"], options);
+        assert!(program.is_match("привет\u{3000}٣").is_match());
+        assert!(!program.is_match("привет x").is_match());
+
+        // Word boundaries and `-w` agree with `\w`.
+        let program = compile_with_options(&["\\bпривет\\b", "\\w+\\>"], options);
+        match program.is_match("x привет") {
+            EvalMatchResult::Match { matches } => assert_eq!(vec![(0, 1), (2, 14)], matches),
+            EvalMatchResult::NoMatch => panic!("expected a match"),
+        }
+        assert!(!compile(&["\\bпривет"]).is_match("привет").is_match());
+        let options = ProgramOptions {
+            word_regexp: true,
+            ..options
+        };
+        assert!(
+            compile_with_options(&["вет"], options)
+                .is_match("при вет")
+                .is_match()
+        );
+        assert!(
+            !compile_with_options(&["вет"], options)
+                .is_match("привет")
+                .is_match()
+        );
+
+        // Back references count chars, not bytes.
+        assert!(compile(&["(ж)\\1"]).is_match("жж").is_match());
+    }
+
+    #[test]
+    fn test_char_escapes() {
+        assert_matches("a\\tb", "xa\tb", &[(1, 4)]);
+        assert_matches("\\x41\\x{1F600}", "A😀", &[(0, 5)]);
+        assert_matches("\\u00e9+", "éé", &[(0, 4)]);
+        assert_matches("[\\x30-\\x39\\t]+", "a1\t2b", &[(1, 4)]);
+        assert_matches("\\0", "a\0", &[(1, 2)]);
+        assert_matches("\\0101", "\x081", &[(0, 2)]);
+        assert_matches("\\Qa.b*\\E+", "a.b**", &[(0, 5)]);
+        assert_matches("\\Q(x)", "a(x)", &[(1, 4)]);
+        assert!(!compile(&["\\Qa.b\\E"]).is_match("axb").is_match());
+    }
+
+    #[test]
+    fn test_inline_flags() {
+        assert_matches("a(?i:b)c", "aBc abC", &[(0, 3)]);
+        assert_matches("(?i)a(?-i:b)", "Ab AB", &[(0, 2)]);
+        assert_matches("(?U)a+", "aaa", &[(0, 1), (1, 2), (2, 3)]);
+        assert_matches("(?U)a+?", "aaa", &[(0, 3)]);
+        assert_matches("a(?#comment)b", "ab", &[(0, 2)]);
+        assert_matches("(?x) a b + # trailing comment", "a bb abb", &[(5, 8)]);
+        assert_matches("(?x)a\\ b [ ]", "a b ", &[(0, 4)]);
+        assert_matches("(?x:a b)c d", "abc d", &[(0, 5)]);
+        assert!(compile(&["(?ms)^a.$"]).is_match("ab").is_match());
+    }
+
+    #[test]
+    fn test_multi_line_subject() {
+        assert_matches("(?m)^b", "b\nb", &[(0, 1), (2, 3)]);
+        assert_matches("^b", "b\nb", &[(0, 1)]);
+        assert_matches("(?m)a$", "a\na", &[(0, 1), (2, 3)]);
+        assert_matches("a$", "a\na", &[(2, 3)]);
+        assert_matches("(?m)\\Ab", "b\nb", &[(0, 1)]);
+        assert_matches("(?m)b\\z", "b\nb", &[(2, 3)]);
+        assert_matches("(?m)^$", "a\n\nb", &[(2, 2)]);
+        assert_matches("(?s)a.b", "a\nb", &[(0, 3)]);
+        assert!(!compile(&["a.b"]).is_match("a\nb").is_match());
+        assert_matches("a[^x]b", "a\nb", &[(0, 3)]);
+    }
+
+    #[test]
+    fn test_zero_width_anchors() {
+        assert_matches("\\bcat\\b", "concat cat cats", &[(7, 10)]);
+        assert_matches("\\Bcat", "concat cat", &[(3, 6)]);
+        assert_matches("\\b", "ab c", &[(0, 0), (2, 2), (3, 3), (4, 4)]);
+        assert_matches("\\<[a-z]", "ab cd", &[(0, 1), (3, 4)]);
+        assert_matches("[a-z]\\>", "ab cd", &[(1, 2), (4, 5)]);
+        assert_matches("\\Aab", "abab", &[(0, 2)]);
+        assert_matches("ab\\z", "abab", &[(2, 4)]);
+        assert!(!compile(&["a\\b\\Bb"]).is_match("ab").is_match());
+        assert!(!compile(&["\\<\\>"]).is_match("a b").is_match());
+        // Repeated zero-width assertions match once at most instead of looping forever.
+        assert_matches("\\b+c", "ab cd", &[(3, 4)]);
+        assert_matches("(a|\\b)*c", "ab cd", &[(3, 4)]);
+        assert_matches("(a?)*b", "aab", &[(0, 3)]);
+        assert!(!compile(&["\\b*x"]).is_match("ab cd").is_match());
+    }
+
+    #[test]
+    fn test_lookaround() {
+        assert_matches("foo(?=bar)", "foobaz foobar", &[(7, 10)]);
+        assert_matches("foo(?!bar)", "foobar foobaz", &[(7, 10)]);
+        assert_matches("(?<=\\$)[0-9]+", "12 $34", &[(4, 6)]);
+        assert_matches("(?<![$0-9])[0-9]+", "$12 34", &[(4, 6)]);
+        assert_matches("(?<=^a)b", "abab", &[(1, 2)]);
+        assert_matches("(?<=a|bc)x", "bcx ax", &[(2, 3), (5, 6)]);
+        assert_matches("x(?=$)", "xx", &[(1, 2)]);
+        // Captures of a positive lookahead are kept.
+        assert!(compile(&["(?=(a))\\1"]).is_match("a").is_match());
+        // The scanned chars are not added to the enclosing groups.
+        assert_matches("(a(?=a))\\1", "aaa", &[(0, 2)]);
+        assert_matches("(a(?<=a))\\1", "aa", &[(0, 2)]);
+        assert_matches("(x(?=(y)))\\2", "xy", &[(0, 2)]);
+        assert!(!compile(&["q(?!u)"]).is_match("queue").is_match());
+        // A quantified assertion matches nothing, repeating it ends.
+        assert_matches("(?=a)*b", "ab", &[(1, 2)]);
+        assert_matches("(?<=a)+b", "ab b", &[(1, 2)]);
+        assert_matches("(?<=a+)b", "aab b", &[(2, 3)]);
+        assert_matches("(?<=\\b\\w{2})c", "ab c abc", &[(7, 8)]);
+        // A bounded lookbehind only tries the starts it can match from, long lines stay fast.
+        let line = format!("{}user=bob took 9", "x".repeat(20_000));
+        assert_matches("(?<=bob) took", &line, &[(20_008, 20_013)]);
+        assert_matches("(?:)*b", "ab", &[(1, 2)]);
+    }
+
+    #[test]
+    fn test_basic_dialect() {
+        let options = ProgramOptions {
+            dialect: Dialect::Basic,
+            ..ProgramOptions::default()
+        };
+
+        let program = compile_with_options(&["a+b", "\\(x\\|y\\)\\{2\\}"], options);
+        assert!(program.is_match("a+b").is_match());
+        assert!(!program.is_match("aab").is_match());
+        assert!(program.is_match("xy").is_match());
+        assert!(!program.is_match("(x|y){2}").is_match());
+    }
+
+    #[test]
+    fn test_fixed_strings() {
+        let options = ProgramOptions {
+            fixed_strings: true,
+            ..ProgramOptions::default()
+        };
+
+        let program = compile_with_options(&["a.c"], options);
+        assert!(program.is_match("xa.c").is_match());
+        assert!(!program.is_match("abc").is_match());
+
+        let program = compile_with_options(&["he", "hers", "é"], options);
+        match program.is_match("ushers é he") {
+            EvalMatchResult::Match { matches } => {
+                assert_eq!(vec![(2, 6), (7, 9), (10, 12)], matches)
+            }
+            EvalMatchResult::NoMatch => panic!("expected a match"),
+        }
+
+        let program = compile_with_options(
+            &["cat"],
+            ProgramOptions {
+                word_regexp: true,
+                ..options
+            },
+        );
+        match program.is_match("cats cat") {
+            EvalMatchResult::Match { matches } => assert_eq!(vec![(5, 8)], matches),
+            EvalMatchResult::NoMatch => panic!("expected a match"),
+        }
+    }
+}
@@ -1,24 +1,39 @@
 use std::fmt::Debug;
 
-use crate::common::Error;
+use crate::error::PatternError;
 
 pub(crate) struct Reader<'a, T> {
     stream: &'a [T],
+    pos: usize,
 }
 
 impl<'a, T> Reader<'a, T> {
     pub(crate) fn new(stream: &'a [T]) -> Self {
-        Self { stream }
+        Self { stream, pos: 0 }
+    }
+
+    /// Offset of the next item from the beginning of the original stream.
+    pub(crate) fn pos(&self) -> usize {
+        self.pos
     }
 
     pub(crate) fn peek(&self) -> Option<&'a T> {
         self.stream.first()
     }
 
-    pub(crate) fn pop(&mut self) -> &'a T {
-        let out = &self.stream[0];
-        self.stream = &self.stream[1..];
-        out
+    pub(crate) fn peek_nth(&self, n: usize) -> Option<&'a T> {
+        self.stream.get(n)
+    }
+
+    pub(crate) fn pop(&mut self) -> Result<&'a T, PatternError> {
+        match self.stream.first() {
+            Some(out) => {
+                self.stream = &self.stream[1..];
+                self.pos += 1;
+                Ok(out)
+            }
+            None => Err(PatternError::UnexpectedEnd { pos: self.pos }),
+        }
     }
 
     pub(crate) fn parse_while<F>(&mut self, pred: F) -> &'a [T]
@@ -27,33 +42,47 @@ impl<'a, T> Reader<'a, T> {
     {
         let mut len = 0usize;
 
-        for i in 0..self.stream.len() {
-            if pred(&self.stream[i]) {
-                len += 1;
-            }
-            break;
+        while len < self.stream.len() && pred(&self.stream[len]) {
+            len += 1;
         }
 
         let out = &self.stream[..len];
         self.stream = &self.stream[len..];
+        self.pos += len;
         out
     }
 
-    pub(crate) fn assert_pop(&mut self, expected: T) -> Result<&'a T, Error>
+    pub(crate) fn assert_pop(&mut self, expected: T) -> Result<&'a T, PatternError>
     where
         T: Debug + PartialEq,
     {
-        let out = &self.stream[0];
-        self.stream = &self.stream[1..];
+        let pos = self.pos;
+        let out = self.pop()?;
 
         if out == &expected {
             Ok(out)
         } else {
-            Err(format!(
-                "Unexpected token. Expected <{:?}>, got <{:?}>.",
-                expected, out
-            )
-            .into())
+            Err(PatternError::UnexpectedToken {
+                pos,
+                expected: format!("{:?}", expected),
+                found: format!("{:?}", out),
+            })
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use crate::reader::Reader;
+
+    #[test]
+    fn test_parse_while() {
+        let chars = "123ab".chars().collect::<Vec<_>>();
+        let mut reader = Reader::new(&chars[..]);
+
+        assert_eq!(&['1', '2', '3'], reader.parse_while(|c| c.is_ascii_digit()));
+        assert_eq!(3, reader.pos());
+        assert!(reader.parse_while(|c| c.is_ascii_digit()).is_empty());
+        assert_eq!(Some(&'a'), reader.peek());
+    }
+}
@@ -90,7 +90,7 @@ impl Transition {
 }
 
 #[allow(dead_code)]
-pub(crate) fn create_dot_file_from_transitions(transitions: &Vec<Transition>) {
+pub(crate) fn create_dot_file_from_transitions(transitions: &[Transition]) {
     let mut f = File::create("./state_machine.dot").unwrap();
 
     f.write_all(b"digraph {{\n").unwrap();
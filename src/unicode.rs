@@ -0,0 +1,67 @@
+use crate::unicode_tables::{GENERAL_CATEGORIES, SCRIPTS};
+
+/// Code point ranges of a `\p{...}` property: a general category (`L`, `Lu`, `Nd`, ...) or a
+/// script (`Greek`, `Cyrillic`, `Han`, ...). The `gc=` and `sc=` forms are accepted too.
+pub(crate) fn property_ranges(name: &str) -> Option<(&'static str, &'static [(u32, u32)])> {
+    let (tables, name) = if let Some(category) = name
+        .strip_prefix("gc=")
+        .or(name.strip_prefix("General_Category="))
+    {
+        (GENERAL_CATEGORIES, category)
+    } else if let Some(script) = name.strip_prefix("sc=").or(name.strip_prefix("Script=")) {
+        (SCRIPTS, script)
+    } else {
+        return GENERAL_CATEGORIES
+            .iter()
+            .chain(SCRIPTS)
+            .find(|(table_name, _)| *table_name == name)
+            .copied();
+    };
+
+    tables
+        .iter()
+        .find(|(table_name, _)| *table_name == name)
+        .copied()
+}
+
+/// Decimal digits of all scripts, `\d` in Unicode mode.
+pub(crate) fn decimal_number_ranges() -> &'static [(u32, u32)] {
+    property_ranges("Nd").unwrap().1
+}
+
+pub(crate) fn ranges_contain(ranges: &[(u32, u32)], c: char) -> bool {
+    let c = c as u32;
+    ranges
+        .binary_search_by(|(start, end)| {
+            if *end < c {
+                std::cmp::Ordering::Less
+            } else if *start > c {
+                std::cmp::Ordering::Greater
+            } else {
+                std::cmp::Ordering::Equal
+            }
+        })
+        .is_ok()
+}
+
+#[cfg(test)]
+mod test {
+    use crate::unicode::{property_ranges, ranges_contain};
+
+    fn has_property(name: &str, c: char) -> bool {
+        ranges_contain(property_ranges(name).unwrap().1, c)
+    }
+
+    #[test]
+    fn test_properties() {
+        assert!(has_property("L", 'ж'));
+        assert!(has_property("Lu", 'Ж'));
+        assert!(!has_property("Lu", 'ж'));
+        assert!(has_property("Nd", '٣'));
+        assert!(has_property("Greek", 'λ'));
+        assert!(!has_property("Greek", 'a'));
+        assert!(has_property("sc=Han", '漢'));
+        assert!(has_property("gc=Zs", ' '));
+        assert!(property_ranges("Klingon").is_none());
+    }
+}
@@ -0,0 +1,244 @@
+use std::{
+    fs,
+    io::Write,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+};
+
+/// Output of a run: exit code, stdout and stderr.
+struct Run {
+    code: i32,
+    stdout: String,
+    stderr: String,
+}
+
+fn grep(args: &[&str], stdin: &[u8]) -> Run {
+    grep_in(Path::new("."), args, stdin)
+}
+
+/// Runs with `dir` as the working directory.
+fn grep_in(dir: &Path, args: &[&str], stdin: &[u8]) -> Run {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_codecrafters-grep"))
+        .current_dir(dir)
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(stdin).unwrap();
+    let output = child.wait_with_output().unwrap();
+
+    Run {
+        code: output.status.code().unwrap(),
+        stdout: String::from_utf8_lossy(&output.stdout).to_string(),
+        stderr: String::from_utf8_lossy(&output.stderr).to_string(),
+    }
+}
+
+/// Writes `content` into a file only this test uses.
+fn temp_file(name: &str, content: &str) -> String {
+    let path: PathBuf =
+        std::env::temp_dir().join(format!("peter_grep_{}_{}", std::process::id(), name));
+    fs::write(&path, content).unwrap();
+    path.to_string_lossy().to_string()
+}
+
+#[test]
+fn test_quiet_prints_nothing() {
+    let file = temp_file("quiet", "abc\n");
+
+    let run = grep(&["-q", "-c", "zzz", &file], b"");
+    assert_eq!((1, ""), (run.code, run.stdout.as_str()));
+
+    let run = grep(&["-q", "-c", "abc", &file], b"");
+    assert_eq!((0, ""), (run.code, run.stdout.as_str()));
+
+    let run = grep(&["-q", "-L", "zzz", &file], b"");
+    assert_eq!((1, ""), (run.code, run.stdout.as_str()));
+
+    let run = grep(&["-q", "-l", "abc", &file], b"");
+    assert_eq!((0, ""), (run.code, run.stdout.as_str()));
+    assert!(run.stderr.is_empty());
+
+    fs::remove_file(file).unwrap();
+}
+
+#[test]
+fn test_input_errors() {
+    let missing = std::env::temp_dir()
+        .join("peter_grep_missing_file")
+        .to_string_lossy()
+        .to_string();
+
+    let run = grep(&["foo", &missing], b"");
+    assert_eq!(2, run.code);
+    assert!(run.stderr.starts_with(&format!("grep: {}: ", missing)));
+
+    // The other files are still searched, but the error decides the exit status.
+    let file = temp_file("errors", "foo\n");
+    let run = grep(&["foo", &missing, &file], b"");
+    assert_eq!((2, format!("{}:foo\n", file)), (run.code, run.stdout));
+    // Unless `-q` found a line.
+    let run = grep(&["-q", "foo", &missing, &file], b"");
+    assert_eq!(0, run.code);
+    fs::remove_file(file).unwrap();
+
+    // Invalid UTF-8 does not stop the reading.
+    let run = grep(&["foo"], b"x\xffy\nfoo\n");
+    assert_eq!((0, "foo\n"), (run.code, run.stdout.as_str()));
+    let run = grep(&["x.y"], b"x\xffy\n");
+    assert_eq!((0, "x\u{fffd}y\n"), (run.code, run.stdout.as_str()));
+    // Offsets are of the bytes read, not of the replacement chars.
+    let run = grep(&["-o", "-b", "a"], b"x\xffab\n");
+    assert_eq!("2:a\n", run.stdout);
+    let run = grep(&["--column", "b"], b"x\xff\xfeab\n");
+    assert_eq!("5:x\u{fffd}\u{fffd}ab\n", run.stdout);
+}
+
+#[test]
+fn test_files_without_match_status() {
+    let file = temp_file("without_match", "abc\n");
+
+    // Exits with success only when a line is selected, like GNU grep 3.5+.
+    let run = grep(&["-L", "zzz", &file], b"");
+    assert_eq!((1, format!("{}\n", file)), (run.code, run.stdout));
+    let run = grep(&["-L", "abc", &file], b"");
+    assert_eq!((0, String::new()), (run.code, run.stdout));
+
+    fs::remove_file(file).unwrap();
+}
+
+fn seq(from: usize, to: usize) -> String {
+    (from..=to).map(|i| format!("{}\n", i)).collect()
+}
+
+#[test]
+fn test_context() {
+    let lines = seq(1, 10);
+
+    let run = grep(&["-n", "-A1", "-e", "3", "-e", "7"], lines.as_bytes());
+    assert_eq!("3:3\n4-4\n--\n7:7\n8-8\n", run.stdout);
+    // The before context is cut at the start of the file.
+    let run = grep(&["-n", "-B3", "2"], lines.as_bytes());
+    assert_eq!("1-1\n2:2\n", run.stdout);
+    // Overlapping groups are joined.
+    let run = grep(&["-C1", "-e", "3", "-e", "5"], lines.as_bytes());
+    assert_eq!("2\n3\n4\n5\n6\n", run.stdout);
+    // The after context runs out at the last selected line of `-m`.
+    let run = grep(&["-m1", "-A2", "-e", "3", "-e", "9"], lines.as_bytes());
+    assert_eq!("3\n4\n5\n", run.stdout);
+
+    // Zero context and `-o` print no context lines, but still separate the groups.
+    let lines = seq(1, 30);
+    let run = grep(&["-C0", "5"], lines.as_bytes());
+    assert_eq!("5\n--\n15\n--\n25\n", run.stdout);
+    let run = grep(&["-o", "-C1", "5"], lines.as_bytes());
+    assert_eq!("5\n--\n5\n--\n5\n", run.stdout);
+    let run = grep(&["-o", "-C1", "-E", "^(5|6|9)$"], lines.as_bytes());
+    assert_eq!("5\n6\n9\n", run.stdout);
+}
+
+#[test]
+fn test_context_multiple_files() {
+    let a = temp_file("context_a", &seq(1, 5));
+    let b = temp_file("context_b", &seq(1, 5));
+
+    // Context never reaches over to the next file.
+    let run = grep(&["-n", "-C1", "-e", "1", "-e", "5", &a, &b], b"");
+    assert_eq!(
+        format!(
+            "{a}:1:1\n{a}-2-2\n--\n{a}-4-4\n{a}:5:5\n--\n{b}:1:1\n{b}-2-2\n--\n{b}-4-4\n{b}:5:5\n"
+        ),
+        run.stdout
+    );
+
+    fs::remove_file(a).unwrap();
+    fs::remove_file(b).unwrap();
+}
+
+#[test]
+fn test_invert_match() {
+    let run = grep(&["-v", "b"], b"a\nb\nc\n");
+    assert_eq!((0, "a\nc\n"), (run.code, run.stdout.as_str()));
+    let run = grep(&["-v", "-n", "b"], b"a\nb\nc\n");
+    assert_eq!("1:a\n3:c\n", run.stdout);
+    let run = grep(&["-v", "-c", "b"], b"a\nb\nc\n");
+    assert_eq!("2\n", run.stdout);
+    // Every line matches, none is selected.
+    let run = grep(&["-v", "-E", "a|b"], b"a\nb\n");
+    assert_eq!((1, ""), (run.code, run.stdout.as_str()));
+
+    let all_match = temp_file("invert_all", "x\nx\n");
+    let some_match = temp_file("invert_some", "x\ny\n");
+    let run = grep(&["-v", "-l", "x", &all_match, &some_match], b"");
+    assert_eq!((0, format!("{}\n", some_match)), (run.code, run.stdout));
+    fs::remove_file(all_match).unwrap();
+    fs::remove_file(some_match).unwrap();
+}
+
+#[test]
+fn test_line_endings() {
+    // Only the `\n` or `\r\n` line ending is removed (`trim_line_ending`), trailing whitespace is part
+    // of the line.
+    let run = grep(&["-x", "a "], b"a \r\na\n");
+    assert_eq!((0, "a \n"), (run.code, run.stdout.as_str()));
+    let run = grep(&["-c", "b$"], b"a\r\nb\r\nb \n");
+    assert_eq!("1\n", run.stdout);
+}
+
+#[test]
+fn test_line_prefixes() {
+    let run = grep(&["-n", "b"], b"a\nab\nb\n");
+    assert_eq!("2:ab\n3:b\n", run.stdout);
+    // Offsets of the line, or of each match with `-o`.
+    let run = grep(&["-b", "b"], b"a\nab\nb\n");
+    assert_eq!("2:ab\n5:b\n", run.stdout);
+    let run = grep(&["-b", "-o", "b"], b"a\nabb\n");
+    assert_eq!("3:b\n4:b\n", run.stdout);
+    // The column of the first match, counted in bytes.
+    let run = grep(&["--column", "b"], b"aab\n\xc3\xa9b\n");
+    assert_eq!("3:aab\n3:\u{e9}b\n", run.stdout);
+    let run = grep(&["-n", "-b", "--column", "b"], b"a\nab\n");
+    assert_eq!("2:2:2:ab\n", run.stdout);
+
+    let file = temp_file("prefixes", "a\nab\n");
+    let other = temp_file("prefixes_other", "");
+    let run = grep(&["-n", "b", &file, &other], b"");
+    assert_eq!(format!("{}:2:ab\n", file), run.stdout);
+    fs::remove_file(file).unwrap();
+    fs::remove_file(other).unwrap();
+}
+
+#[test]
+fn test_max_count() {
+    let run = grep(&["-m2", "a"], b"a1\nb\na2\na3\n");
+    assert_eq!((0, "a1\na2\n"), (run.code, run.stdout.as_str()));
+    let run = grep(&["-m2", "-c", "a"], b"a1\na2\na3\n");
+    assert_eq!("2\n", run.stdout);
+    let run = grep(&["-m0", "a"], b"a\n");
+    assert_eq!((1, ""), (run.code, run.stdout.as_str()));
+    // The trailing context of the last selected line is still printed.
+    let run = grep(&["-m1", "-A1", "-n", "a"], b"a1\nb\na2\n");
+    assert_eq!("1:a1\n2-b\n", run.stdout);
+
+    // The count is per file.
+    let file = temp_file("max_count", "a\na\n");
+    let run = grep(&["-m1", "a", &file, &file], b"");
+    assert_eq!(format!("{f}:a\n{f}:a\n", f = file), run.stdout);
+    fs::remove_file(file).unwrap();
+}
+
+#[test]
+fn test_recursive_without_files() {
+    let dir = std::env::temp_dir().join(format!("peter_grep_{}_recursive", std::process::id()));
+    fs::create_dir_all(dir.join("sub")).unwrap();
+    fs::write(dir.join("sub").join("a.txt"), "needle\n").unwrap();
+    fs::write(dir.join("b.txt"), "hay\n").unwrap();
+
+    // The working directory is searched, its paths are printed without `./`.
+    let run = grep_in(&dir, &["-r", "needle"], b"");
+    assert_eq!((0, "sub/a.txt:needle\n"), (run.code, run.stdout.as_str()));
+
+    fs::remove_dir_all(dir).unwrap();
+}